@@ -0,0 +1,15 @@
+#![no_main]
+use image::RgbaImage;
+use libfuzzer_sys::fuzz_target;
+use uniclip_proto::clip_msg::MsgType;
+
+// whatever passes decode has to be applicable without panicking
+fuzz_target!(|data: &[u8]| {
+    if let Ok(msg) = uniclip_net::decode(data, None) {
+        if msg.typ() == MsgType::Image {
+            let image = msg.image.expect("validated image has data");
+            RgbaImage::from_raw(image.width, image.height, image.data)
+                .expect("validated image has matching dimensions");
+        }
+    }
+});
@@ -0,0 +1,10 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use uniclip_net::Cipher;
+
+// the same payload sealed with the group key, reaching the decoder behind decryption
+fuzz_target!(|data: &[u8]| {
+    let cipher = Cipher::new("fuzz", "secret");
+    let _ = uniclip_net::decode(data, Some(&cipher));
+    let _ = uniclip_net::decode(&cipher.encrypt(data), Some(&cipher));
+});
@@ -0,0 +1,18 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use prost::Message;
+use uniclip_net::mailbox::{Mailbox, MailboxConfig, MailboxRequest, MailboxResponse};
+
+// a mailbox serves requests from any peer, the input is split into several requests
+fuzz_target!(|data: &[u8]| {
+    let mut mailbox = Mailbox::new(MailboxConfig {
+        size: 4,
+        ..Default::default()
+    });
+    for chunk in data.split(|&b| b == 0xff) {
+        if let Ok(request) = MailboxRequest::decode(chunk) {
+            let response = mailbox.handle(request);
+            MailboxResponse::decode(response.encode_to_vec().as_slice()).unwrap();
+        }
+    }
+});
@@ -0,0 +1,460 @@
+use crate::{clip::ClipMsg, groups::Mode, peers, publish, State};
+use anyhow::bail;
+use image::{DynamicImage, ImageOutputFormat, RgbaImage};
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    io::{BufRead, BufReader, Cursor, Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{self, Receiver, RecvTimeoutError, Sender},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+use tracing::{debug, error, info};
+use uniclip_proto::clip_msg::MsgType;
+
+const KEEP_ALIVE: Duration = Duration::from_secs(15);
+// for the request, event streams only write afterwards
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+// event streams included
+const MAX_CONNECTIONS: usize = 32;
+// of the request line and each header
+const MAX_LINE: u64 = 8 * 1024;
+const MAX_HEADERS: usize = 64;
+
+// json view of an item, image data is only served by GET /clip?format=png
+#[derive(Serialize)]
+struct Item {
+    id: u32,
+    #[serde(rename = "type")]
+    typ: &'static str,
+    timestamp: Option<u64>,
+    // device the item was copied on
+    device: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    text: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    width: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    height: Option<u32>,
+}
+
+impl From<&uniclip_proto::ClipMsg> for Item {
+    fn from(msg: &uniclip_proto::ClipMsg) -> Self {
+        Self {
+            id: msg.id,
+            typ: match msg.typ() {
+                MsgType::Text => "text",
+                MsgType::Image => "image",
+                MsgType::Clear => "clear",
+            },
+            timestamp: msg.timestamp,
+            device: msg.device.clone(),
+            text: msg.text.clone(),
+            width: msg.image.as_ref().map(|image| image.width),
+            height: msg.image.as_ref().map(|image| image.height),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Peer {
+    peer_id: String,
+    name: Option<String>,
+    os: Option<String>,
+    app_version: Option<String>,
+    online: bool,
+    last_seen: Option<u64>,
+    in_group: bool,
+    // names of the groups we share
+    groups: Vec<String>,
+    mailbox: bool,
+    version: Option<u32>,
+    capabilities: Vec<String>,
+    compatible: bool,
+}
+
+// fans incoming items out to the open event streams
+#[derive(Default)]
+pub struct Events {
+    subscribers: Mutex<Vec<Sender<String>>>,
+}
+
+impl Events {
+    pub fn send(&self, msg: &uniclip_proto::ClipMsg) {
+        let mut subscribers = self.subscribers.lock().unwrap();
+        if subscribers.is_empty() {
+            return;
+        }
+        let event = serde_json::to_string(&Item::from(msg)).unwrap();
+        subscribers.retain(|tx| tx.send(event.clone()).is_ok());
+    }
+
+    fn subscribe(&self) -> Receiver<String> {
+        let (tx, rx) = mpsc::channel();
+        self.subscribers.lock().unwrap().push(tx);
+        rx
+    }
+}
+
+struct Request {
+    method: String,
+    path: String,
+    query: HashMap<String, String>,
+    headers: HashMap<String, String>,
+    body: Vec<u8>,
+}
+
+struct Response {
+    status: &'static str,
+    content_type: &'static str,
+    body: Vec<u8>,
+}
+
+impl Response {
+    fn new(status: &'static str, content_type: &'static str, body: impl Into<Vec<u8>>) -> Self {
+        Self {
+            status,
+            content_type,
+            body: body.into(),
+        }
+    }
+
+    fn json(value: &impl Serialize) -> Self {
+        Self::new(
+            "200 OK",
+            "application/json",
+            serde_json::to_vec(value).unwrap(),
+        )
+    }
+
+    fn error(status: &'static str, message: impl Into<String>) -> Self {
+        Self::new(status, "text/plain; charset=utf-8", message.into())
+    }
+
+    fn write(&self, mut stream: &TcpStream) -> std::io::Result<()> {
+        write!(
+            stream,
+            "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            self.status,
+            self.content_type,
+            self.body.len()
+        )?;
+        stream.write_all(&self.body)
+    }
+}
+
+pub fn serve(addr: &str, state: State) -> anyhow::Result<()> {
+    let listener = TcpListener::bind(addr)?;
+    info!(%addr, "serving http api");
+
+    let connections = Arc::new(AtomicUsize::new(0));
+    for stream in listener.incoming().flatten() {
+        if connections.fetch_add(1, Ordering::SeqCst) >= MAX_CONNECTIONS {
+            connections.fetch_sub(1, Ordering::SeqCst);
+            debug!("too many api connections");
+            let _ =
+                Response::error("503 Service Unavailable", "too many connections").write(&stream);
+            continue;
+        }
+        if let Err(err) = stream.set_read_timeout(Some(READ_TIMEOUT)) {
+            connections.fetch_sub(1, Ordering::SeqCst);
+            debug!("set api read timeout failed: {err}");
+            continue;
+        }
+
+        let state = state.clone();
+        let connections = connections.clone();
+        std::thread::spawn(move || {
+            let _connection = Connection(connections);
+            let request = match read_request(&stream, &state) {
+                Ok(request) => request,
+                Err(response) => {
+                    let _ = response.write(&stream);
+                    return;
+                }
+            };
+            debug!(method = %request.method, path = %request.path, "api request");
+
+            let response = match (request.method.as_str(), request.path.as_str()) {
+                ("GET", "/events") => return stream_events(&stream, &state),
+                ("GET", "/clip") => get_clip(&request, &state),
+                ("POST", "/clip") => post_clip(&request, &state),
+                ("GET", "/history") => get_history(&state),
+                ("GET", "/peers") => get_peers(&state),
+                _ => Response::error("404 Not Found", "not found"),
+            };
+            if let Err(err) = response.write(&stream) {
+                debug!("write api response failed: {err}");
+            }
+        });
+    }
+    Ok(())
+}
+
+// counts an open connection until dropped
+struct Connection(Arc<AtomicUsize>);
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+// the token is checked before the body is read
+fn read_request(stream: &TcpStream, state: &State) -> Result<Request, Response> {
+    let bad_request = |err: anyhow::Error| Response::error("400 Bad Request", err.to_string());
+    let mut reader = BufReader::new(stream);
+    let mut request = read_head(&mut reader).map_err(bad_request)?;
+
+    if !authorized(&request, state) {
+        return Err(Response::error("401 Unauthorized", "invalid token"));
+    }
+
+    request.body = read_body(&mut reader, &request, state).map_err(bad_request)?;
+    Ok(request)
+}
+
+fn read_line(reader: &mut BufReader<&TcpStream>) -> anyhow::Result<String> {
+    let mut line = String::new();
+    reader.by_ref().take(MAX_LINE).read_line(&mut line)?;
+    if !line.ends_with('\n') {
+        bail!("line too long or incomplete");
+    }
+    Ok(line)
+}
+
+fn read_head(reader: &mut BufReader<&TcpStream>) -> anyhow::Result<Request> {
+    let line = read_line(reader)?;
+    let mut parts = line.split_whitespace();
+    let (method, target) = match (parts.next(), parts.next()) {
+        (Some(method), Some(target)) => (method.to_string(), target),
+        _ => bail!("invalid request line"),
+    };
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let query = query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect();
+
+    let mut headers = HashMap::new();
+    loop {
+        let line = read_line(reader)?;
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if headers.len() == MAX_HEADERS {
+            bail!("too many headers");
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            headers.insert(name.trim().to_lowercase(), value.trim().to_string());
+        }
+    }
+
+    Ok(Request {
+        method,
+        path: path.to_string(),
+        query,
+        headers,
+        body: Vec::new(),
+    })
+}
+
+fn read_body(
+    reader: &mut BufReader<&TcpStream>,
+    request: &Request,
+    state: &State,
+) -> anyhow::Result<Vec<u8>> {
+    let length = match request.headers.get("content-length") {
+        Some(length) => length.parse::<usize>()?,
+        None => 0,
+    };
+    let limit = state.settings.read().unwrap().limits.largest();
+    if length > limit {
+        bail!("body too large");
+    }
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body)?;
+    Ok(body)
+}
+
+// EventSource can't set headers, so the token may also come as ?token=
+fn authorized(request: &Request, state: &State) -> bool {
+    let settings = state.settings.read().unwrap();
+    let token = match &settings.api.token {
+        Some(token) => token.as_bytes(),
+        None => return false,
+    };
+    let given = request
+        .headers
+        .get("authorization")
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .or_else(|| request.query.get("token").map(String::as_str))
+        .unwrap_or_default()
+        .as_bytes();
+
+    given.len() == token.len()
+        && given
+            .iter()
+            .zip(token)
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+fn get_clip(request: &Request, state: &State) -> Response {
+    let latest = match state.history.latest() {
+        Some(latest) if latest.typ() != MsgType::Clear => latest,
+        _ => return Response::error("404 Not Found", "clipboard is empty"),
+    };
+
+    match request.query.get("format").map(String::as_str) {
+        Some("json") => Response::json(&Item::from(&latest)),
+        None | Some("text") => match latest.typ() {
+            MsgType::Text => Response::new(
+                "200 OK",
+                "text/plain; charset=utf-8",
+                latest.text().as_bytes(),
+            ),
+            _ => Response::error("406 Not Acceptable", "latest item is not text"),
+        },
+        Some("png") => match latest.image.as_ref().and_then(encode_png) {
+            Some(png) => Response::new("200 OK", "image/png", png),
+            None => Response::error("406 Not Acceptable", "latest item is not an image"),
+        },
+        Some(format) => Response::error("400 Bad Request", format!("unknown format {format:?}")),
+    }
+}
+
+fn post_clip(request: &Request, state: &State) -> Response {
+    let content_type = request
+        .headers
+        .get("content-type")
+        .map(String::as_str)
+        .unwrap_or_default();
+
+    let msg = if content_type.starts_with("image/") {
+        if let Err(response) = check_dimensions(&request.body, state) {
+            return response;
+        }
+        match image::load_from_memory(&request.body) {
+            Ok(image) => {
+                let image = image.to_rgba8();
+                ClipMsg::Image((
+                    image.width() as usize,
+                    image.height() as usize,
+                    image.into_raw(),
+                ))
+            }
+            Err(err) => return Response::error("400 Bad Request", format!("invalid image: {err}")),
+        }
+    } else {
+        match String::from_utf8(request.body.clone()) {
+            Ok(text) => ClipMsg::Text(text),
+            Err(_) => return Response::error("400 Bad Request", "text must be utf-8"),
+        }
+    };
+
+    match publish(state, msg, Mode::Auto) {
+        Ok(id) => Response::json(&serde_json::json!({ "id": id })),
+        Err(reason) => Response::error("422 Unprocessable Entity", reason),
+    }
+}
+
+// a small file may declare dimensions that take gigabytes once decoded
+fn check_dimensions(data: &[u8], state: &State) -> Result<(), Response> {
+    let (width, height) = image::io::Reader::new(Cursor::new(data))
+        .with_guessed_format()
+        .map_err(|err| Response::error("400 Bad Request", format!("invalid image: {err}")))?
+        .into_dimensions()
+        .map_err(|err| Response::error("400 Bad Request", format!("invalid image: {err}")))?;
+    // decoded as rgba
+    let size = width as u64 * height as u64 * 4;
+    let limit = state.settings.read().unwrap().limits.image as u64;
+    if size > limit {
+        return Err(Response::error(
+            "413 Payload Too Large",
+            format!("image of {width}x{height} is larger than the limit once decoded"),
+        ));
+    }
+    Ok(())
+}
+
+fn get_history(state: &State) -> Response {
+    let items: Vec<_> = state.history.entries().iter().map(Item::from).collect();
+    Response::json(&items)
+}
+
+fn get_peers(state: &State) -> Response {
+    match peers(state) {
+        Ok(peers) => {
+            let groups = state.groups.read().unwrap();
+            Response::json(
+                &peers
+                    .into_iter()
+                    .map(|peer| Peer {
+                        peer_id: peer.peer_id.to_string(),
+                        name: peer.device.as_ref().map(|device| device.name.clone()),
+                        os: peer.device.as_ref().map(|device| device.os.clone()),
+                        app_version: peer.device.as_ref().map(|device| device.version.clone()),
+                        online: peer.online,
+                        last_seen: peer.last_seen,
+                        in_group: peer.in_group,
+                        groups: peer
+                            .groups
+                            .iter()
+                            .map(|topic| groups.name(topic).unwrap_or(topic).to_string())
+                            .collect(),
+                        mailbox: peer.mailbox,
+                        version: peer.version.as_ref().map(|version| version.version),
+                        capabilities: peer
+                            .version
+                            .iter()
+                            .flat_map(|version| &version.capabilities)
+                            .map(ToString::to_string)
+                            .collect(),
+                        compatible: peer.version.map_or(true, |version| version.compatible()),
+                    })
+                    .collect::<Vec<_>>(),
+            )
+        }
+        Err(err) => Response::error("503 Service Unavailable", err.to_string()),
+    }
+}
+
+fn stream_events(mut stream: &TcpStream, state: &State) {
+    let events = state.events.subscribe();
+    let result = (|| -> std::io::Result<()> {
+        write!(
+            stream,
+            "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\nConnection: keep-alive\r\n\r\n"
+        )?;
+        loop {
+            match events.recv_timeout(KEEP_ALIVE) {
+                Ok(event) => write!(stream, "event: clip\ndata: {event}\n\n")?,
+                Err(RecvTimeoutError::Timeout) => write!(stream, ": keep-alive\n\n")?,
+                Err(RecvTimeoutError::Disconnected) => return Ok(()),
+            }
+            stream.flush()?;
+        }
+    })();
+    if let Err(err) = result {
+        debug!("event stream closed: {err}");
+    }
+}
+
+fn encode_png(image: &uniclip_proto::clip_msg::ImageData) -> Option<Vec<u8>> {
+    let image = RgbaImage::from_raw(image.width, image.height, image.data.clone())?;
+    let mut png = Vec::new();
+    match DynamicImage::ImageRgba8(image).write_to(&mut png, ImageOutputFormat::Png) {
+        Ok(_) => Some(png),
+        Err(err) => {
+            error!("encode png failed: {err}");
+            None
+        }
+    }
+}
@@ -21,20 +21,68 @@ impl ClipMsg {
     }
 }
 
+// where items are read from and written to, the system clipboard outside of tests
+pub trait Backend: Send + Sync {
+    fn get_text(&self) -> anyhow::Result<String>;
+    fn set_text(&self, text: &str) -> anyhow::Result<()>;
+    fn get_image(&self) -> anyhow::Result<(usize, usize, Vec<u8>)>;
+    fn set_image(&self, image: (usize, usize, &[u8])) -> anyhow::Result<()>;
+}
+
+pub struct System;
+
+impl Backend for System {
+    fn get_text(&self) -> anyhow::Result<String> {
+        Ok(Clipboard::new()?.get_text()?)
+    }
+
+    fn set_text(&self, text: &str) -> anyhow::Result<()> {
+        let mut clip = Clipboard::new()?;
+        clip.set_text(text.to_owned())?;
+        // keep the clipboard alive for a while, some platforms serve it from this process
+        sleep(Duration::from_secs(1));
+        Ok(())
+    }
+
+    fn get_image(&self) -> anyhow::Result<(usize, usize, Vec<u8>)> {
+        let image = Clipboard::new()?.get_image()?;
+        Ok((image.width, image.height, image.bytes.into_owned()))
+    }
+
+    fn set_image(&self, image: (usize, usize, &[u8])) -> anyhow::Result<()> {
+        let mut clip = Clipboard::new()?;
+        clip.set_image(ImageData {
+            width: image.0,
+            height: image.1,
+            bytes: image.2.into(),
+        })?;
+        sleep(Duration::from_secs(1));
+        Ok(())
+    }
+}
+
 pub struct Clip {
+    backend: Box<dyn Backend>,
     text: RwLock<String>,
     image_info: RwLock<(usize, usize, usize)>, // width, height, len
+    current: RwLock<u32>,                      // id of the synced item, 0 if unknown
 
-    delay_millis: u64,
+    poll_interval: RwLock<Duration>,
 }
 
 impl Clip {
-    pub fn new() -> Self {
+    pub fn new(poll_interval: Duration) -> Self {
+        Self::with_backend(Box::new(System), poll_interval)
+    }
+
+    pub fn with_backend(backend: Box<dyn Backend>, poll_interval: Duration) -> Self {
         Self {
+            backend,
             text: RwLock::new("".into()),
             image_info: RwLock::new((0, 0, 0)),
+            current: RwLock::new(0),
 
-            delay_millis: 200,
+            poll_interval: RwLock::new(poll_interval),
         }
     }
 
@@ -42,54 +90,72 @@ impl Clip {
     where
         F: Fn(ClipMsg),
     {
-        let mut clip = Clipboard::new().unwrap();
         loop {
-            if let Ok(text) = clip.get_text() {
+            if let Ok(text) = self.backend.get_text() {
                 let origin = { self.text.read().unwrap().to_owned() };
                 if text != origin {
                     {
                         *self.text.write().unwrap() = text.clone();
+                        *self.current.write().unwrap() = 0;
                     }
                     on_clipboard_change(ClipMsg::text(text));
                 }
             }
 
-            if let Ok(image) = clip.get_image() {
-                let image_info = (image.width, image.height, image.bytes.len());
+            if let Ok((width, height, bytes)) = self.backend.get_image() {
+                let image_info = (width, height, bytes.len());
                 let origin = { self.image_info.read().unwrap().to_owned() };
                 if image_info != origin {
                     {
                         *self.image_info.write().unwrap() = image_info;
+                        *self.current.write().unwrap() = 0;
                     }
-                    on_clipboard_change(ClipMsg::image(image.width, image.height, &image.bytes));
+                    on_clipboard_change(ClipMsg::Image((width, height, bytes)));
                 }
             }
 
-            sleep(Duration::from_millis(self.delay_millis));
+            let poll_interval = { *self.poll_interval.read().unwrap() };
+            sleep(poll_interval);
         }
     }
 
-    pub fn set_text(self: Arc<Self>, text: &str) -> anyhow::Result<()> {
-        let mut clip = Clipboard::new().unwrap();
-        clip.set_text(text.to_owned())?;
-        {
-            *self.text.write().unwrap() = text.to_owned();
+    // what is on the clipboard right now
+    pub fn read(&self) -> Option<ClipMsg> {
+        match self.backend.get_text() {
+            Ok(text) if !text.is_empty() => Some(ClipMsg::Text(text)),
+            _ => self.backend.get_image().ok().map(ClipMsg::Image),
         }
-        sleep(Duration::from_secs(1));
-        Ok(())
+    }
+
+    pub fn set_text(self: Arc<Self>, text: &str) -> anyhow::Result<()> {
+        // remember it first so the poll loop doesn't take it for a local copy
+        *self.text.write().unwrap() = text.to_owned();
+        self.backend.set_text(text)
     }
 
     pub fn set_image(self: Arc<Self>, image: (usize, usize, &[u8])) -> anyhow::Result<()> {
-        let mut clip = Clipboard::new().unwrap();
-        clip.set_image(ImageData {
-            width: image.0,
-            height: image.1,
-            bytes: image.2.into(),
-        })?;
+        *self.image_info.write().unwrap() = (image.0, image.1, image.2.len());
+        self.backend.set_image(image)
+    }
+
+    pub fn clear(self: Arc<Self>) -> anyhow::Result<()> {
         {
-            *self.image_info.write().unwrap() = (image.0, image.1, image.2.len());
+            *self.text.write().unwrap() = String::new();
+            *self.image_info.write().unwrap() = (0, 0, 0);
+            *self.current.write().unwrap() = 0;
         }
-        sleep(Duration::from_secs(1));
-        Ok(())
+        self.backend.set_text("")
+    }
+
+    pub fn set_poll_interval(&self, poll_interval: Duration) {
+        *self.poll_interval.write().unwrap() = poll_interval;
+    }
+
+    pub fn current(&self) -> u32 {
+        *self.current.read().unwrap()
+    }
+
+    pub fn set_current(&self, id: u32) {
+        *self.current.write().unwrap() = id;
     }
 }
@@ -0,0 +1,64 @@
+cfg_if::cfg_if! {
+    if #[cfg(windows)] {
+        const EXCLUDE_FORMAT: &str = "ExcludeClipboardContentFromMonitorProcessing";
+
+        pub fn is_concealed() -> bool {
+            match clipboard_win::register_format(EXCLUDE_FORMAT) {
+                Some(format) => clipboard_win::is_format_avail(format.get()),
+                None => false,
+            }
+        }
+
+    } else if #[cfg(target_os = "linux")] {
+        use std::process::Command;
+
+        const PASSWORD_MANAGER_HINT: &str = "x-kde-passwordManagerHint";
+
+        fn targets() -> Option<String> {
+            let output = if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+                Command::new("wl-paste").arg("--list-types").output()
+            } else {
+                Command::new("xclip")
+                    .args(["-selection", "clipboard", "-o", "-t", "TARGETS"])
+                    .output()
+            };
+            output
+                .ok()
+                .filter(|output| output.status.success())
+                .map(|output| String::from_utf8_lossy(&output.stdout).into_owned())
+        }
+
+        pub fn is_concealed() -> bool {
+            targets()
+                .map(|targets| targets.lines().any(|target| target.trim() == PASSWORD_MANAGER_HINT))
+                .unwrap_or(false)
+        }
+
+    } else if #[cfg(target_os = "macos")] {
+        use objc::{class, msg_send, runtime::Object, sel, sel_impl};
+        use std::{ffi::CStr, os::raw::c_char};
+
+        const CONCEALED_TYPE: &[u8] = b"org.nspasteboard.ConcealedType";
+
+        pub fn is_concealed() -> bool {
+            unsafe {
+                let pasteboard: *mut Object = msg_send![class!(NSPasteboard), generalPasteboard];
+                let types: *mut Object = msg_send![pasteboard, types];
+                if types.is_null() {
+                    return false;
+                }
+                let count: usize = msg_send![types, count];
+                (0..count).any(|i| {
+                    let typ: *mut Object = msg_send![types, objectAtIndex: i];
+                    let name: *const c_char = msg_send![typ, UTF8String];
+                    !name.is_null() && CStr::from_ptr(name).to_bytes() == CONCEALED_TYPE
+                })
+            }
+        }
+
+    } else {
+        pub fn is_concealed() -> bool {
+            false
+        }
+    }
+}
@@ -0,0 +1,247 @@
+use crate::{
+    clip::ClipMsg,
+    filter::{Filter, FilterConfig},
+    settings::Settings,
+    transform::Transforms,
+};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tracing::warn;
+
+// the group of the top-level topic and secret
+pub const DEFAULT_GROUP: &str = "default";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    Both,
+    // publish local copies, ignore what the others copy
+    Send,
+    // apply what the others copy, keep local copies out
+    Receive,
+}
+
+impl Default for Direction {
+    fn default() -> Self {
+        Direction::Both
+    }
+}
+
+impl Direction {
+    pub fn sends(self) -> bool {
+        self != Direction::Receive
+    }
+
+    pub fn receives(self) -> bool {
+        self != Direction::Send
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Mode {
+    Auto,
+    // publish: only what is shared, apply: stage until applied
+    Manual,
+}
+
+impl Default for Mode {
+    fn default() -> Self {
+        Mode::Auto
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GroupSettings {
+    pub name: String,
+    pub topic: String,
+    pub secret: Option<String>,
+    pub direction: Direction,
+    // publish every local copy, or only what is shared
+    pub publish: Mode,
+    // apply incoming items right away, or stage them
+    pub apply: Mode,
+    // local copies it drops stay out of this group, on top of the global filter
+    pub filter: FilterConfig,
+}
+
+impl Default for GroupSettings {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            topic: String::new(),
+            secret: None,
+            direction: Direction::default(),
+            publish: Mode::default(),
+            apply: Mode::default(),
+            // the global filter ran already
+            filter: FilterConfig {
+                builtin: false,
+                patterns: Vec::new(),
+                password_manager_hints: false,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    Text,
+    Image,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RouteSettings {
+    // regex on the text, images never match one
+    pub pattern: Option<String>,
+    pub format: Option<Format>,
+    // group names, an empty list keeps matching items local
+    pub groups: Vec<String>,
+}
+
+struct Group {
+    name: String,
+    topic: String,
+    direction: Direction,
+    publish: Mode,
+    apply: Mode,
+    filter: Filter,
+}
+
+struct Route {
+    pattern: Option<Regex>,
+    format: Option<Format>,
+    groups: Vec<String>,
+}
+
+impl Route {
+    fn matches(&self, msg: &ClipMsg) -> bool {
+        let format = match msg {
+            ClipMsg::Text(_) => Format::Text,
+            ClipMsg::Image(_) => Format::Image,
+        };
+        if matches!(self.format, Some(expected) if expected != format) {
+            return false;
+        }
+        match (&self.pattern, msg) {
+            (None, _) => true,
+            (Some(pattern), ClipMsg::Text(text)) => pattern.is_match(text),
+            (Some(_), ClipMsg::Image(_)) => false,
+        }
+    }
+}
+
+/// The groups of the settings, which of them local copies go to
+/// and which of them items are taken from.
+pub struct Groups {
+    groups: Vec<Group>,
+    routes: Vec<Route>,
+    transforms: Arc<Transforms>,
+}
+
+impl Groups {
+    pub fn new(settings: &Settings) -> anyhow::Result<Self> {
+        let mut groups = vec![Group {
+            name: DEFAULT_GROUP.to_string(),
+            topic: settings.topic.clone(),
+            direction: settings.direction,
+            publish: settings.publish,
+            apply: settings.apply,
+            // the global filter covers it
+            filter: Filter::new(&GroupSettings::default().filter)?,
+        }];
+        for group in &settings.groups {
+            groups.push(Group {
+                name: group.name.clone(),
+                topic: group.topic.clone(),
+                direction: group.direction,
+                publish: group.publish,
+                apply: group.apply,
+                filter: Filter::new(&group.filter)?,
+            });
+        }
+
+        let routes = settings
+            .routes
+            .iter()
+            .map(|route| {
+                Ok(Route {
+                    pattern: route.pattern.as_deref().map(Regex::new).transpose()?,
+                    format: route.format,
+                    groups: route.groups.clone(),
+                })
+            })
+            .collect::<anyhow::Result<_>>()?;
+
+        Ok(Self {
+            groups,
+            routes,
+            transforms: Arc::new(Transforms::new(&settings.transforms)),
+        })
+    }
+
+    /// Topics a local item is published to: the groups of the first matching route,
+    /// every group that sends when none matches. Copies go to the groups that publish
+    /// automatically, shared items to the ones that publish manually.
+    pub fn route(&self, msg: &ClipMsg, mode: Mode) -> Result<Vec<String>, String> {
+        let candidates: Vec<_> = match self.routes.iter().find(|route| route.matches(msg)) {
+            Some(route) => self
+                .groups
+                .iter()
+                .filter(|group| route.groups.contains(&group.name))
+                .collect(),
+            None => self.groups.iter().collect(),
+        };
+
+        let mut topics = Vec::new();
+        for group in candidates {
+            if !group.direction.sends() || group.publish != mode {
+                continue;
+            }
+            if let Err(reason) = group.filter.check(msg) {
+                warn!(group = %group.name, %reason, "keep local clipboard item out of group");
+                continue;
+            }
+            topics.push(group.topic.clone());
+        }
+        if topics.is_empty() {
+            return Err(match mode {
+                Mode::Auto => "no group to publish to".to_string(),
+                Mode::Manual => "no group to share with, see `publish` in the settings".to_string(),
+            });
+        }
+        Ok(topics)
+    }
+
+    /// The name of the group an item came through, if it takes items from it.
+    pub fn receives(&self, topic: &str) -> Result<&str, &'static str> {
+        match self.groups.iter().find(|group| group.topic == topic) {
+            Some(group) if group.direction.receives() => Ok(&group.name),
+            Some(_) => Err("group only sends"),
+            None => Err("unknown group"),
+        }
+    }
+
+    // whether items of the group wait to be applied
+    pub fn stages(&self, topic: &str) -> bool {
+        self.groups
+            .iter()
+            .any(|group| group.topic == topic && group.apply == Mode::Manual)
+    }
+
+    pub fn name(&self, topic: &str) -> Option<&str> {
+        self.groups
+            .iter()
+            .find(|group| group.topic == topic)
+            .map(|group| group.name.as_str())
+    }
+
+    // shared, commands may take a while and a reload should not wait for them
+    pub fn transforms(&self) -> Arc<Transforms> {
+        self.transforms.clone()
+    }
+}
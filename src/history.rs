@@ -0,0 +1,52 @@
+use std::{collections::VecDeque, sync::RwLock};
+use uniclip_proto::ClipMsg;
+
+pub struct History {
+    entries: RwLock<VecDeque<ClipMsg>>,
+    capacity: usize,
+}
+
+impl History {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: RwLock::new(VecDeque::with_capacity(capacity)),
+            capacity,
+        }
+    }
+
+    // kept in timestamp order, items from old peers without one go last
+    pub fn push(&self, msg: ClipMsg) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut entries = self.entries.write().unwrap();
+        let position = match msg.timestamp {
+            Some(timestamp) => entries
+                .iter()
+                .rposition(|entry| entry.timestamp.map_or(true, |t| t <= timestamp))
+                .map_or(0, |position| position + 1),
+            None => entries.len(),
+        };
+        entries.insert(position, msg);
+        if entries.len() > self.capacity {
+            entries.pop_front();
+        }
+    }
+
+    pub fn remove(&self, id: u32) {
+        self.entries.write().unwrap().retain(|msg| msg.id != id);
+    }
+
+    pub fn contains(&self, id: u32) -> bool {
+        self.entries.read().unwrap().iter().any(|msg| msg.id == id)
+    }
+
+    // oldest first
+    pub fn entries(&self) -> Vec<ClipMsg> {
+        self.entries.read().unwrap().iter().cloned().collect()
+    }
+
+    pub fn latest(&self) -> Option<ClipMsg> {
+        self.entries.read().unwrap().back().cloned()
+    }
+}
@@ -0,0 +1,19 @@
+use crate::ipc::{Control, Request};
+use anyhow::anyhow;
+use std::sync::mpsc::Sender;
+use tauri_hotkey::{parse_hotkey, HotkeyManager};
+use tracing::info;
+
+/// Shares the current clipboard whenever `hotkey` is pressed, for as long as
+/// the returned manager lives.
+pub fn register(hotkey: &str, control_tx: Sender<Request>) -> anyhow::Result<HotkeyManager> {
+    let parsed = parse_hotkey(hotkey).map_err(|err| anyhow!("{hotkey:?}: {err:?}"))?;
+    let mut manager = HotkeyManager::new();
+    manager
+        .register(parsed, move || {
+            info!("share hotkey pressed");
+            let _ = control_tx.send(Request::new(Control::Share));
+        })
+        .map_err(|err| anyhow!("register {hotkey:?}: {err:?}"))?;
+    Ok(manager)
+}
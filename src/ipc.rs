@@ -0,0 +1,114 @@
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::mpsc::{self, Sender},
+    time::Duration,
+};
+
+pub const DEFAULT_ADDR: &str = "127.0.0.1:34568";
+// clients send their command right after connecting
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_LINE: u64 = 4096;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Control {
+    ClearEverywhere,
+    Reload,
+    Peers,
+    // the current clipboard to one peer
+    SendTo(String),
+    // the current clipboard to the groups that publish manually
+    Share,
+    // the item a group that applies manually staged
+    ApplyStaged,
+}
+
+impl Control {
+    fn parse(line: &str) -> Option<Self> {
+        match line.trim() {
+            "clear" => Some(Control::ClearEverywhere),
+            "reload" => Some(Control::Reload),
+            "peers" => Some(Control::Peers),
+            "share" => Some(Control::Share),
+            "apply" => Some(Control::ApplyStaged),
+            line => line
+                .strip_prefix("send ")
+                .map(|to| Control::SendTo(to.trim().to_string())),
+        }
+    }
+
+    fn command(&self) -> String {
+        match self {
+            Control::ClearEverywhere => "clear".to_string(),
+            Control::Reload => "reload".to_string(),
+            Control::Peers => "peers".to_string(),
+            Control::SendTo(to) => format!("send {to}"),
+            Control::Share => "share".to_string(),
+            Control::ApplyStaged => "apply".to_string(),
+        }
+    }
+}
+
+pub struct Request {
+    pub control: Control,
+    reply_tx: Option<Sender<String>>,
+}
+
+impl Request {
+    pub fn new(control: Control) -> Self {
+        Self {
+            control,
+            reply_tx: None,
+        }
+    }
+
+    pub fn reply(&self, reply: impl Into<String>) {
+        if let Some(reply_tx) = &self.reply_tx {
+            let _ = reply_tx.send(reply.into());
+        }
+    }
+}
+
+pub fn serve(addr: &str, control_tx: Sender<Request>) -> anyhow::Result<()> {
+    let listener = TcpListener::bind(addr)?;
+    for stream in listener.incoming().flatten() {
+        // one at a time, a client that sends nothing must not hold up the others
+        if stream.set_read_timeout(Some(READ_TIMEOUT)).is_err() {
+            continue;
+        }
+        let mut line = String::new();
+        let read = BufReader::new(&stream).take(MAX_LINE).read_line(&mut line);
+        if read.is_err() {
+            continue;
+        }
+
+        let reply = match Control::parse(&line) {
+            Some(control) => {
+                let (reply_tx, reply_rx) = mpsc::channel();
+                let request = Request {
+                    control,
+                    reply_tx: Some(reply_tx),
+                };
+                match control_tx.send(request) {
+                    Ok(_) => reply_rx
+                        .recv_timeout(Duration::from_secs(10))
+                        .unwrap_or_else(|err| format!("error: {err}")),
+                    Err(err) => format!("error: {err}"),
+                }
+            }
+            None => format!("error: unknown command {:?}", line.trim()),
+        };
+        let _ = writeln!(&stream, "{reply}");
+    }
+    Ok(())
+}
+
+pub fn request(addr: &str, control: Control) -> anyhow::Result<String> {
+    let mut stream = TcpStream::connect(addr)?;
+    writeln!(stream, "{}", control.command())?;
+
+    // replies may span several lines, the connection is closed after one
+    let mut reply = String::new();
+    stream.read_to_string(&mut reply)?;
+    Ok(reply.trim_end().to_string())
+}
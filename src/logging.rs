@@ -0,0 +1,83 @@
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, Ordering},
+};
+use tracing::warn;
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_subscriber::EnvFilter;
+
+static LOG_CONTENT: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    pub level: String,
+    pub file: Option<PathBuf>,
+    // only honored by debug builds
+    pub content: bool,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            level: "info".to_string(),
+            file: None,
+            content: false,
+        }
+    }
+}
+
+pub fn init(config: &LogConfig) -> anyhow::Result<Option<WorkerGuard>> {
+    let filter =
+        EnvFilter::try_from_default_env().or_else(|_| EnvFilter::try_new(&config.level))?;
+
+    let guard = match &config.file {
+        Some(path) => {
+            let dir = path
+                .parent()
+                .filter(|dir| !dir.as_os_str().is_empty())
+                .unwrap_or_else(|| Path::new("."));
+            let name = path
+                .file_name()
+                .ok_or_else(|| anyhow!("invalid log file: {}", path.display()))?;
+            let (writer, guard) =
+                tracing_appender::non_blocking(tracing_appender::rolling::never(dir, name));
+            tracing_subscriber::fmt()
+                .with_env_filter(filter)
+                .with_writer(writer)
+                .with_ansi(false)
+                .init();
+            Some(guard)
+        }
+        None => {
+            tracing_subscriber::fmt().with_env_filter(filter).init();
+            None
+        }
+    };
+
+    if config.content {
+        if cfg!(debug_assertions) {
+            LOG_CONTENT.store(true, Ordering::Relaxed);
+            warn!("clipboard content logging enabled");
+        } else {
+            warn!("clipboard content logging is only available in debug builds");
+        }
+    }
+
+    Ok(guard)
+}
+
+pub struct Content<'a>(pub &'a str);
+
+impl fmt::Display for Content<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if LOG_CONTENT.load(Ordering::Relaxed) {
+            write!(f, "{:?}", self.0)
+        } else {
+            write!(f, "<redacted {} bytes>", self.0.len())
+        }
+    }
+}
@@ -3,96 +3,809 @@
     windows_subsystem = "windows"
 )]
 
-use config::Config;
-use serde::Deserialize;
-use std::sync::Arc;
+use clap::{Parser, Subcommand};
+use rand::Rng;
+use std::{
+    path::PathBuf,
+    process,
+    sync::{Arc, Mutex, RwLock},
+    thread::sleep,
+    time::Duration,
+};
+use tracing::{debug, error, info, info_span, warn, Instrument};
 
+mod api;
 mod clip;
 use clip::*;
+mod conceal;
+mod filter;
+use filter::Filter;
+mod groups;
+use groups::{Groups, Mode};
+mod history;
+mod hotkey;
+use history::History;
+mod ipc;
+use ipc::{Control, Request};
+mod logging;
+use logging::Content;
+mod notification;
+mod osc52;
+mod outbox;
+mod settings;
+use settings::Settings;
+#[cfg(test)]
+mod tests;
+mod transform;
 mod tray;
+mod watch;
 
-#[derive(Debug, Default, Deserialize)]
-pub struct Settings {
-    domain: String,
+#[derive(Debug, Parser)]
+#[clap(name = "uniclip")]
+struct Opt {
+    /// Settings file, defaults to settings.toml in the platform config dir
+    #[clap(long, global = true)]
+    config: Option<PathBuf>,
+
+    #[clap(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Clear the latest synced item on every peer
+    Clear,
+    /// Reload the settings file of the running daemon
+    Reload,
+    /// List devices with their names, protocol versions and when they were last seen
+    Peers,
+    /// Send the current clipboard to a single peer instead of the whole group
+    Send {
+        /// Device name or peer id, see `uniclip peers`
+        #[clap(long)]
+        to: String,
+    },
+    /// Share the current clipboard with the groups that publish manually
+    Share,
+    /// Apply the item staged by a group that applies manually
+    Apply,
+    /// Manage the settings file
+    #[clap(subcommand)]
+    Config(ConfigCommand),
+    /// Sync OSC 52 clipboard sequences of a terminal program with the group
+    ///
+    /// Runs COMMAND in a pty, or filters stdin to stdout without one. Sequences
+    /// setting the clipboard are published, queries are answered with the latest
+    /// text of the group. Needs no display.
+    Osc52 {
+        /// Program to wrap, e.g. `uniclip osc52 -- tmux`
+        #[clap(last = true)]
+        command: Vec<String>,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum ConfigCommand {
+    /// Write a settings file with a fresh topic and secret
+    Init {
+        /// Overwrite an existing settings file
+        #[clap(long)]
+        force: bool,
+    },
+    /// Validate the settings file
+    Check,
+}
+
+#[derive(Clone)]
+struct State {
+    settings_path: PathBuf,
+    settings: Arc<RwLock<Settings>>,
+    filter: Arc<RwLock<Filter>>,
+    groups: Arc<RwLock<Groups>>,
+
+    clip: Arc<Clip>,
+    history: Arc<History>,
+    // the latest item of a group that applies manually
+    staged: Arc<Mutex<Option<uniclip_proto::ClipMsg>>>,
+    events: Arc<api::Events>,
+    // local items, so the clipboard never waits for the network
+    outbox: Arc<outbox::Outbox>,
+    to_net_tx: tokio::sync::mpsc::Sender<uniclip_net::Command>,
+}
+
+fn new_id() -> u32 {
+    rand::thread_rng().gen_range(1..=u32::MAX)
+}
+
+fn exit_on_error<T>(result: anyhow::Result<T>) -> T {
+    match result {
+        Ok(value) => value,
+        Err(err) => {
+            eprintln!("{err:#}");
+            process::exit(1);
+        }
+    }
 }
 
 fn main() {
-    let settings = Config::builder()
-        .add_source(config::File::with_name("settings"))
-        .add_source(config::Environment::with_prefix("UNICLIP"))
-        .build()
-        .unwrap();
+    let opt = Opt::parse();
+    let settings_path = exit_on_error(opt.config.map(Ok).unwrap_or_else(settings::default_path));
+
+    let mut osc52_command = None;
+    match opt.command {
+        Some(Command::Clear) => return request(Control::ClearEverywhere),
+        Some(Command::Reload) => return request(Control::Reload),
+        Some(Command::Peers) => return request(Control::Peers),
+        Some(Command::Send { to }) => return request(Control::SendTo(to)),
+        Some(Command::Share) => return request(Control::Share),
+        Some(Command::Apply) => return request(Control::ApplyStaged),
+        Some(Command::Config(ConfigCommand::Init { force })) => {
+            exit_on_error(Settings::init(&settings_path, force));
+            println!("settings written to {}", settings_path.display());
+            return;
+        }
+        Some(Command::Config(ConfigCommand::Check)) => {
+            exit_on_error(Settings::load(&settings_path));
+            if let Some(warning) = Settings::permission_warning(&settings_path) {
+                println!("warning: {warning}");
+            }
+            println!("{} is valid", settings_path.display());
+            return;
+        }
+        Some(Command::Osc52 { command }) => osc52_command = Some(command),
+        None => {}
+    }
 
-    let settings = settings.try_deserialize::<Settings>().unwrap();
+    if !settings_path.exists() {
+        eprintln!(
+            "{} not found, create it with `uniclip config init`",
+            settings_path.display()
+        );
+        process::exit(1);
+    }
+    let settings = exit_on_error(Settings::load(&settings_path));
+    // logging to stdout would garble the wrapped terminal
+    let _log_guard = match osc52_command {
+        Some(_) if settings.log.file.is_none() => None,
+        _ => exit_on_error(logging::init(&settings.log)),
+    };
+    info!(path = %settings_path.display(), "settings loaded");
+    if let Some(warning) = Settings::permission_warning(&settings_path) {
+        warn!("{warning}");
+    }
+
+    let filter = Filter::new(&settings.filter).unwrap();
+    let groups = Groups::new(&settings).unwrap();
+    let mut net_config = exit_on_error(settings.net_config());
+    // a daemon on the same machine would be another swarm with the same peer id
+    if osc52_command.is_some() {
+        net_config.dir = net_config.dir.join("osc52");
+    }
 
     let (from_net_tx, from_net_rx) = std::sync::mpsc::channel();
     let (to_net_tx, to_net_rx) = tokio::sync::mpsc::channel(10);
+    let (control_tx, control_rx) = std::sync::mpsc::channel();
 
     std::thread::spawn(move || {
-        let net_config = uniclip_net::Config {
-            dir: ".".to_string(),
-            topic: settings.domain.to_string(),
-            relay_server_address: None,
-            relay_server_peer_id: None,
-        };
-
         tokio::runtime::Builder::new_multi_thread()
             .enable_all()
             .build()
             .unwrap()
-            .block_on(async move { uniclip_net::trans(net_config, from_net_tx, to_net_rx).await });
+            .block_on(
+                uniclip_net::trans(net_config, from_net_tx, to_net_rx)
+                    .instrument(info_span!("net")),
+            );
     });
 
-    let clip = Arc::new(Clip::new());
-    let monitor_clip = clip.clone();
+    let state = State {
+        clip: Arc::new(Clip::new(settings.poll_interval())),
+        history: Arc::new(History::new(settings.history.size)),
+        staged: Arc::new(Mutex::new(None)),
+        events: Arc::new(api::Events::default()),
+        settings_path,
+        settings: Arc::new(RwLock::new(settings)),
+        filter: Arc::new(RwLock::new(filter)),
+        groups: Arc::new(RwLock::new(groups)),
+        outbox: outbox::Outbox::spawn(to_net_tx.clone()),
+        to_net_tx,
+    };
 
-    std::thread::spawn(move || {
-        monitor_clip.notify(|msg| match msg {
-            ClipMsg::Text(text) => {
-                println!("local clipboard notify text: {text}");
-                let mut clip_msg = uniclip_proto::ClipMsg {
-                    text: Some(text),
-                    ..Default::default()
+    if let Some(command) = osc52_command {
+        return osc52::run(state, from_net_rx, command);
+    }
+
+    spawn_sync(&state, from_net_rx);
+
+    {
+        let control_tx = control_tx.clone();
+        std::thread::spawn(move || {
+            if let Err(err) = ipc::serve(ipc::DEFAULT_ADDR, control_tx) {
+                error!("control socket failed: {err}");
+            }
+        });
+    }
+
+    let api = state.settings.read().unwrap().api.clone();
+    if api.enabled {
+        let state = state.clone();
+        std::thread::spawn(move || {
+            let _span = info_span!("api").entered();
+            if let Err(err) = api::serve(&api.listen, state) {
+                error!("http api failed: {err}");
+            }
+        });
+    }
+
+    {
+        let control_tx = control_tx.clone();
+        let settings_path = state.settings_path.clone();
+        std::thread::spawn(move || {
+            if let Err(err) = watch::watch(&settings_path, control_tx) {
+                error!("watching settings failed: {err}");
+            }
+        });
+    }
+
+    // unregistered when dropped, so it lives as long as the tray
+    let hotkey = state.settings.read().unwrap().hotkey.clone();
+    let _hotkey = hotkey.and_then(|hotkey| {
+        hotkey::register(&hotkey, control_tx.clone())
+            .map_err(|err| error!("share hotkey failed: {err:#}"))
+            .ok()
+    });
+
+    let state_for_tray = state.clone();
+    std::thread::spawn(move || loop {
+        if let Ok(request) = control_rx.recv() {
+            handle(&state, request);
+        }
+    });
+
+    tray::start_tray(control_tx, state_for_tray);
+}
+
+// publishes local copies and applies items from the net
+fn spawn_sync(state: &State, from_net_rx: std::sync::mpsc::Receiver<uniclip_net::Received>) {
+    {
+        let state = state.clone();
+        std::thread::spawn(move || {
+            let _span = info_span!("clip").entered();
+            state.clip.clone().notify(|msg| {
+                if let Ok(id) = publish(&state, msg, Mode::Auto) {
+                    state.clip.set_current(id);
+                }
+            });
+        });
+    }
+
+    {
+        let state = state.clone();
+        std::thread::spawn(move || {
+            let _span = info_span!("apply").entered();
+            for received in from_net_rx {
+                let (group, stages) = {
+                    let groups = state.groups.read().unwrap();
+                    let group = groups.name(&received.topic).unwrap_or_default().to_string();
+                    (group, groups.stages(&received.topic))
                 };
-                clip_msg.set_typ(uniclip_proto::clip_msg::MsgType::Text);
-                to_net_tx.blocking_send(clip_msg).unwrap();
-            }
-            ClipMsg::Image(image) => {
-                println!("local clipboard notify image");
-                let mut clip_msg = uniclip_proto::ClipMsg {
-                    image: Some(uniclip_proto::clip_msg::ImageData {
-                        data: image.2,
-                        width: image.0 as u32,
-                        height: image.1 as u32,
-                    }),
-                    ..Default::default()
+                let transforms = state.groups.read().unwrap().transforms();
+                let origin = received.origin;
+                let mut msg = match accept(&state, received) {
+                    Some(msg) => msg,
+                    None => continue,
                 };
-                clip_msg.set_typ(uniclip_proto::clip_msg::MsgType::Image);
-                to_net_tx.blocking_send(clip_msg).unwrap();
+                if msg.typ() == uniclip_proto::clip_msg::MsgType::Clear {
+                    apply(&state, msg);
+                    continue;
+                }
+                transforms.apply(&mut msg, Some(&group), false);
+                if let Err(reason) = state.settings.read().unwrap().accepts(&msg) {
+                    warn!(id = msg.id, %reason, "drop transformed clip from net");
+                    continue;
+                }
+                if stale(&state, &msg, origin) {
+                    info!(id = msg.id, "fetched from a mailbox, keep in history only");
+                    state.events.send(&msg);
+                    state.history.push(msg);
+                    continue;
+                }
+
+                let stage = stages
+                    || state
+                        .settings
+                        .read()
+                        .unwrap()
+                        .notifications
+                        .requires_accept(&msg);
+                notification::received(&state, &msg, &group, stage);
+                if stage {
+                    info!(
+                        id = msg.id,
+                        "stage clip from net, apply it with `uniclip apply`"
+                    );
+                    *state.staged.lock().unwrap() = Some(msg);
+                } else {
+                    apply(&state, msg);
+                }
             }
         });
-    });
+    }
+}
 
-    std::thread::spawn(move || {
-        loop {
-            if let Ok(msg) = from_net_rx.recv() {
-                match msg.typ() {
-                    uniclip_proto::clip_msg::MsgType::Text => {
-                        let text = msg.text();
-                        println!("receive from net: {text}");
-                        clip.clone().set_text(text).unwrap();
-                    }
-                    uniclip_proto::clip_msg::MsgType::Image => {
-                        let image = msg.image.unwrap();
-                        println!("receive from net: image");
-                        clip.clone()
-                            .set_image((image.width as usize, image.height as usize, &image.data))
-                            .unwrap();
+fn request(control: Control) {
+    match ipc::request(ipc::DEFAULT_ADDR, control) {
+        Ok(reply) => println!("{reply}"),
+        Err(err) => println!("uniclip is not running: {err}"),
+    }
+}
+
+fn peers(state: &State) -> anyhow::Result<Vec<uniclip_net::PeerInfo>> {
+    let (reply_tx, reply_rx) = tokio::sync::oneshot::channel();
+    state
+        .to_net_tx
+        .blocking_send(uniclip_net::Command::Peers(reply_tx))
+        .map_err(|_| anyhow::anyhow!("network stopped"))?;
+    reply_rx
+        .blocking_recv()
+        .map_err(|_| anyhow::anyhow!("network stopped"))
+}
+
+// callers that took `msg` from the clipboard mark it as the current item, items posted
+// to the api never were on it
+fn publish(state: &State, msg: ClipMsg, mode: Mode) -> Result<u32, String> {
+    let topics = state
+        .groups
+        .read()
+        .unwrap()
+        .route(&msg, mode)
+        .map_err(|reason| {
+            debug!(%reason, "keep local clipboard item");
+            reason
+        })?;
+    let clip_msg = prepare(state, msg)?;
+    let id = clip_msg.id;
+    state.history.push(clip_msg.clone());
+
+    // groups whose transforms give the same item share one publish
+    let mut outgoing: Vec<(uniclip_proto::ClipMsg, Vec<String>)> = Vec::new();
+    let (names, transforms) = {
+        let groups = state.groups.read().unwrap();
+        let names: Vec<_> = topics
+            .iter()
+            .map(|topic| groups.name(topic).map(str::to_string))
+            .collect();
+        (names, groups.transforms())
+    };
+    for (topic, name) in topics.into_iter().zip(names) {
+        let mut clip_msg = clip_msg.clone();
+        transforms.apply(&mut clip_msg, name.as_deref(), true);
+        // a transform may have grown the item past the limits
+        if let Err(reason) = state.settings.read().unwrap().accepts(&clip_msg) {
+            warn!(id, %topic, %reason, "keep transformed item local");
+            continue;
+        }
+        match outgoing.iter_mut().find(|(other, _)| *other == clip_msg) {
+            Some((_, topics)) => topics.push(topic),
+            None => outgoing.push((clip_msg, vec![topic])),
+        }
+    }
+    let commands = outgoing
+        .into_iter()
+        .map(|(clip_msg, topics)| uniclip_net::Command::PublishTo { topics, clip_msg })
+        .collect();
+    state.outbox.publish(commands).map_err(|reason| {
+        error!(id, %reason, "publish failed");
+        reason.to_string()
+    })?;
+    Ok(id)
+}
+
+// a peer id, or the name of a connected device
+fn resolve_peer(state: &State, to: &str) -> anyhow::Result<(uniclip_net::PeerId, String)> {
+    if let Ok(peer_id) = to.parse() {
+        return Ok((peer_id, to.to_string()));
+    }
+    let matching: Vec<_> = peers(state)?
+        .into_iter()
+        .filter(|peer| peer.online)
+        .filter_map(|peer| Some((peer.peer_id, peer.device?.name)))
+        .filter(|(_, name)| name.eq_ignore_ascii_case(to.trim()))
+        .collect();
+    match matching.as_slice() {
+        [] => anyhow::bail!("no connected device named {to:?}"),
+        [(peer_id, name)] => Ok((*peer_id, name.clone())),
+        _ => anyhow::bail!("several devices are named {to:?}, use the peer id"),
+    }
+}
+
+fn send(state: &State, to: &str) -> anyhow::Result<String> {
+    let (to, name) = resolve_peer(state, to)?;
+    let msg = state
+        .clip
+        .read()
+        .ok_or_else(|| anyhow::anyhow!("clipboard is empty"))?;
+    let mut clip_msg = prepare(state, msg).map_err(anyhow::Error::msg)?;
+    // the network picks the group, so only transforms for all groups apply
+    let transforms = state.groups.read().unwrap().transforms();
+    transforms.apply(&mut clip_msg, None, true);
+    if let Err(reason) = state.settings.read().unwrap().accepts(&clip_msg) {
+        anyhow::bail!("transformed item: {reason}");
+    }
+
+    let (reply_tx, reply_rx) = tokio::sync::oneshot::channel();
+    state
+        .to_net_tx
+        .blocking_send(uniclip_net::Command::Send {
+            to,
+            clip_msg,
+            reply_tx,
+        })
+        .map_err(|_| anyhow::anyhow!("network stopped"))?;
+    reply_rx
+        .blocking_recv()
+        .map_err(|_| anyhow::anyhow!("network stopped"))?
+        .map_err(anyhow::Error::msg)?;
+    Ok(format!("sent to {name}"))
+}
+
+fn share(state: &State) -> anyhow::Result<String> {
+    let msg = state
+        .clip
+        .read()
+        .ok_or_else(|| anyhow::anyhow!("clipboard is empty"))?;
+    let id = publish(state, msg, Mode::Manual).map_err(anyhow::Error::msg)?;
+    state.clip.set_current(id);
+    Ok("shared".to_string())
+}
+
+// the staged item, or only the one with `id`, as a notification may be older than it
+fn apply_staged(state: &State, id: Option<u32>) -> anyhow::Result<String> {
+    let msg = {
+        let mut staged = state.staged.lock().unwrap();
+        match (staged.as_ref(), id) {
+            (Some(msg), Some(id)) if msg.id != id => None,
+            _ => staged.take(),
+        }
+    }
+    .ok_or_else(|| anyhow::anyhow!("nothing staged"))?;
+    apply(state, msg);
+    Ok("applied".to_string())
+}
+
+// a local item as it goes out, unless filters or limits keep it here
+fn prepare(state: &State, msg: ClipMsg) -> Result<uniclip_proto::ClipMsg, String> {
+    {
+        let filter = state.filter.read().unwrap();
+        if let Err(reason) = filter.check(&msg) {
+            warn!(%reason, dropped = filter.dropped(), "drop local clipboard item");
+            return Err(reason.to_string());
+        }
+    }
+
+    let mut clip_msg = match msg {
+        ClipMsg::Text(text) => {
+            info!("local clipboard notify text: {}", Content(&text));
+            let mut clip_msg = uniclip_proto::ClipMsg {
+                text: Some(text),
+                ..Default::default()
+            };
+            clip_msg.set_typ(uniclip_proto::clip_msg::MsgType::Text);
+            clip_msg
+        }
+        ClipMsg::Image(image) => {
+            info!(
+                width = image.0,
+                height = image.1,
+                "local clipboard notify image"
+            );
+            let mut clip_msg = uniclip_proto::ClipMsg {
+                image: Some(uniclip_proto::clip_msg::ImageData {
+                    data: image.2,
+                    width: image.0 as u32,
+                    height: image.1 as u32,
+                }),
+                ..Default::default()
+            };
+            clip_msg.set_typ(uniclip_proto::clip_msg::MsgType::Image);
+            clip_msg
+        }
+    };
+
+    {
+        let settings = state.settings.read().unwrap();
+        if let Err(reason) = settings.accepts(&clip_msg) {
+            warn!(%reason, "drop local clipboard item");
+            return Err(reason.to_string());
+        }
+        clip_msg.id = new_id();
+        clip_msg.device = Some(settings.device_name.trim().to_string());
+        clip_msg.ttl = settings.ttl;
+        clip_msg.timestamp = Some(uniclip_net::now_millis());
+    }
+    Ok(clip_msg)
+}
+
+// the item, unless its group only sends or the settings rule it out
+fn accept(state: &State, received: uniclip_net::Received) -> Option<uniclip_proto::ClipMsg> {
+    let msg = received.clip_msg;
+    match state.groups.read().unwrap().receives(&received.topic) {
+        Ok(group) => debug!(id = msg.id, %group, "clip from net"),
+        Err(reason) => {
+            warn!(id = msg.id, topic = %received.topic, %reason, "drop clip from net");
+            return None;
+        }
+    }
+    // items published to several of our groups arrive once per group
+    if msg.typ() != uniclip_proto::clip_msg::MsgType::Clear
+        && (state.clip.current() == msg.id || state.history.contains(msg.id))
+    {
+        debug!(id = msg.id, "already received through another group");
+        return None;
+    }
+    if let Err(reason) = state.settings.read().unwrap().accepts(&msg) {
+        warn!(id = msg.id, %reason, "drop clip from net");
+        return None;
+    }
+    Some(msg)
+}
+
+// of a mailbox fetch only the newest item is applied, and not if something newer was
+// copied since; clocks of live peers may be off, so their items are never compared
+fn stale(state: &State, msg: &uniclip_proto::ClipMsg, origin: uniclip_net::Origin) -> bool {
+    let newest = match origin {
+        uniclip_net::Origin::Live => return false,
+        uniclip_net::Origin::Mailbox { newest } => newest,
+    };
+    if msg.typ() == uniclip_proto::clip_msg::MsgType::Clear {
+        return false;
+    }
+    let older = matches!(
+        (msg.timestamp, state.history.latest().and_then(|latest| latest.timestamp)),
+        (Some(timestamp), Some(latest)) if timestamp < latest
+    );
+    !newest || older
+}
+
+fn apply(state: &State, msg: uniclip_proto::ClipMsg) {
+    state.events.send(&msg);
+
+    let clip = &state.clip;
+    let applied = match msg.typ() {
+        uniclip_proto::clip_msg::MsgType::Text => {
+            let text = msg.text();
+            info!(
+                id = msg.id,
+                from = msg.device(),
+                "receive from net: {}",
+                Content(text)
+            );
+            clip.clone().set_text(text)
+        }
+        uniclip_proto::clip_msg::MsgType::Image => {
+            // uniclip_net::decode checks this already, apply must not panic regardless
+            let image = match &msg.image {
+                Some(image) => image,
+                None => {
+                    warn!(id = msg.id, "drop image without data");
+                    return;
+                }
+            };
+            info!(
+                id = msg.id,
+                from = msg.device(),
+                width = image.width,
+                height = image.height,
+                "receive from net: image"
+            );
+            clip.clone()
+                .set_image((image.width as usize, image.height as usize, &image.data))
+        }
+        uniclip_proto::clip_msg::MsgType::Clear => {
+            info!(id = msg.id, from = msg.device(), "receive from net: clear");
+            {
+                let mut staged = state.staged.lock().unwrap();
+                if staged.as_ref().map(|staged| staged.id) == Some(msg.id) {
+                    *staged = None;
+                }
+            }
+            if clip.current() == msg.id {
+                if let Err(err) = clip.clone().clear() {
+                    error!(id = msg.id, "clear clipboard failed: {err}");
+                }
+            }
+            state.history.remove(msg.id);
+            return;
+        }
+    };
+    if let Err(err) = applied {
+        error!(id = msg.id, "set clipboard failed: {err}");
+        return;
+    }
+
+    clip.set_current(msg.id);
+    if let Some(ttl) = msg.ttl {
+        expire(state.clone(), msg.id, ttl);
+    }
+    state.history.push(msg);
+}
+
+fn handle(state: &State, request: Request) {
+    match &request.control {
+        Control::ClearEverywhere => {
+            // the item on the clipboard, history is ordered by clocks of other peers
+            let id = match state.clip.current() {
+                0 => state.history.latest().map(|latest| latest.id),
+                id => Some(id),
+            };
+            if let Err(err) = state.clip.clone().clear() {
+                error!("clear clipboard failed: {err}");
+            }
+            match id {
+                Some(id) => {
+                    info!(id, "clear everywhere");
+                    state.history.remove(id);
+
+                    let mut clip_msg = uniclip_proto::ClipMsg {
+                        id,
+                        ..Default::default()
+                    };
+                    clip_msg.set_typ(uniclip_proto::clip_msg::MsgType::Clear);
+                    match state
+                        .outbox
+                        .control(uniclip_net::Command::Publish(clip_msg))
+                    {
+                        Ok(()) => request.reply("ok"),
+                        Err(reason) => request.reply(format!("cleared here, {reason}")),
                     }
                 }
+                None => request.reply("nothing to clear"),
             }
         }
-    });
+        Control::Peers => match peers(state) {
+            Ok(peers) if peers.is_empty() => request.reply("no peers"),
+            Ok(peers) => {
+                let groups = state.groups.read().unwrap();
+                request.reply(
+                    peers
+                        .iter()
+                        .map(|peer| describe_peer(&groups, peer))
+                        .collect::<Vec<_>>()
+                        .join("\n"),
+                )
+            }
+            Err(err) => request.reply(format!("error: {err:#}")),
+        },
+        Control::Share => match share(state) {
+            Ok(reply) => {
+                info!("{reply}");
+                request.reply(reply);
+            }
+            Err(err) => {
+                warn!("share failed: {err:#}");
+                request.reply(format!("error: {err:#}"));
+            }
+        },
+        Control::ApplyStaged => match apply_staged(state, None) {
+            Ok(reply) => request.reply(reply),
+            Err(err) => request.reply(format!("error: {err:#}")),
+        },
+        Control::SendTo(to) => match send(state, to) {
+            Ok(reply) => {
+                info!("{reply}");
+                request.reply(reply);
+            }
+            Err(err) => {
+                error!(%to, "send failed: {err:#}");
+                request.reply(format!("error: {err:#}"));
+            }
+        },
+        Control::Reload => match reload(state) {
+            Ok(reply) => {
+                info!("{reply}");
+                request.reply(reply);
+            }
+            Err(err) => {
+                error!("reload failed: {err:#}");
+                request.reply(format!("error: {err:#}"));
+            }
+        },
+    }
+}
 
-    tray::start_tray();
+fn describe_peer(groups: &Groups, peer: &uniclip_net::PeerInfo) -> String {
+    let mut line = match &peer.device {
+        Some(device) => format!("{device} {}", peer.peer_id),
+        None => peer.peer_id.to_string(),
+    };
+    match &peer.version {
+        Some(version) => {
+            line.push_str(&format!(" v{}", version.version));
+            let capabilities: Vec<_> = version
+                .capabilities
+                .iter()
+                .map(ToString::to_string)
+                .collect();
+            line.push_str(&format!(" [{}]", capabilities.join(",")));
+            if !version.compatible() {
+                line.push_str(" incompatible, needs an update");
+            } else if version.version > uniclip_net::version::PROTOCOL_VERSION {
+                line.push_str(" newer, update this device");
+            }
+        }
+        None => line.push_str(" unidentified"),
+    }
+    let names: Vec<_> = peer
+        .groups
+        .iter()
+        .map(|topic| groups.name(topic).unwrap_or(topic))
+        .collect();
+    if !names.is_empty() {
+        line.push_str(&format!(" groups [{}]", names.join(",")));
+    }
+    if peer.mailbox {
+        line.push_str(" mailbox");
+    }
+    match (peer.online, peer.last_seen) {
+        (true, _) => line.push_str(" online"),
+        (false, Some(last_seen)) => line.push_str(&format!(" last seen {}", ago(last_seen))),
+        (false, None) => line.push_str(" offline"),
+    }
+    line
+}
+
+// "3m ago" for a unix timestamp in milliseconds
+fn ago(millis: u64) -> String {
+    let secs = uniclip_net::now_millis().saturating_sub(millis) / 1000;
+    match secs {
+        0..=59 => "just now".to_string(),
+        60..=3599 => format!("{}m ago", secs / 60),
+        3600..=86399 => format!("{}h ago", secs / 3600),
+        _ => format!("{}d ago", secs / 86400),
+    }
+}
+
+fn reload(state: &State) -> anyhow::Result<String> {
+    let settings = Settings::load(&state.settings_path)?;
+    if let Some(warning) = Settings::permission_warning(&state.settings_path) {
+        warn!("{warning}");
+    }
+    let net_config = settings.net_config()?;
+    let groups = Groups::new(&settings)?;
+
+    state
+        .filter
+        .write()
+        .unwrap()
+        .reconfigure(&settings.filter)?;
+    *state.groups.write().unwrap() = groups;
+    state.clip.set_poll_interval(settings.poll_interval());
+    state
+        .to_net_tx
+        .blocking_send(uniclip_net::Command::Reconfigure(net_config))
+        .map_err(|_| anyhow::anyhow!("network stopped"))?;
+
+    let restart_required = state.settings.read().unwrap().restart_required(&settings);
+    *state.settings.write().unwrap() = settings;
+
+    if restart_required.is_empty() {
+        Ok("settings reloaded".to_string())
+    } else {
+        Ok(format!(
+            "settings reloaded, restart to apply: {}",
+            restart_required.join(", ")
+        ))
+    }
+}
+
+fn expire(state: State, id: u32, ttl: u32) {
+    std::thread::spawn(move || {
+        sleep(Duration::from_secs(ttl as u64));
+        if state.clip.current() == id {
+            debug!(id, "clipboard item expired");
+            if let Err(err) = state.clip.clone().clear() {
+                error!(id, "clear clipboard failed: {err}");
+            }
+        }
+        state.history.remove(id);
+    });
 }
@@ -0,0 +1,108 @@
+use crate::State;
+use notify_rust::Notification;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+use uniclip_proto::{clip_msg::MsgType, ClipMsg};
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NotificationConfig {
+    pub enabled: bool,
+    // characters of text shown, 0 shows only the length so that content stays
+    // off the screen like it stays out of the logs
+    pub preview: usize,
+    // bytes, larger items are staged until accepted like those of groups with apply = "manual"
+    pub accept_above: Option<usize>,
+}
+
+impl Default for NotificationConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            preview: 0,
+            accept_above: None,
+        }
+    }
+}
+
+impl NotificationConfig {
+    pub fn requires_accept(&self, msg: &ClipMsg) -> bool {
+        let size = match msg.typ() {
+            MsgType::Text => msg.text().len(),
+            MsgType::Image => msg.image.as_ref().map_or(0, |image| image.data.len()),
+            MsgType::Clear => return false,
+        };
+        matches!(self.accept_above, Some(limit) if size > limit)
+    }
+}
+
+/// Tells about an item from `group`, with an "Accept" button when it is staged.
+pub fn received(state: &State, msg: &ClipMsg, group: &str, staged: bool) {
+    let config = state.settings.read().unwrap().notifications.clone();
+    if !config.enabled {
+        return;
+    }
+
+    let summary = format!(
+        "Clipboard from {} ({group})",
+        msg.device.as_deref().unwrap_or("another device")
+    );
+    let mut body = preview(msg, config.preview);
+    if staged {
+        body.push_str("\nWaiting to be accepted");
+    }
+
+    let mut notification = Notification::new();
+    notification
+        .appname("uniclip")
+        .summary(&summary)
+        .body(&body);
+    if !staged {
+        if let Err(err) = notification.show() {
+            warn!("show notification failed: {err}");
+        }
+        return;
+    }
+
+    notification.action("accept", "Accept");
+    let state = state.clone();
+    let id = msg.id;
+    // waiting for the click blocks until the notification is closed
+    std::thread::spawn(move || match notification.show() {
+        Ok(handle) => wait_for_accept(handle, state, id),
+        Err(err) => warn!("show notification failed: {err}"),
+    });
+}
+
+fn preview(msg: &ClipMsg, chars: usize) -> String {
+    match msg.typ() {
+        MsgType::Text if chars == 0 => format!("{} characters", msg.text().chars().count()),
+        MsgType::Text => {
+            let text: String = msg.text().split_whitespace().collect::<Vec<_>>().join(" ");
+            match text.char_indices().nth(chars) {
+                Some((end, _)) => format!("{}…", &text[..end]),
+                None => text,
+            }
+        }
+        MsgType::Image => match &msg.image {
+            Some(image) => format!("image {}x{}", image.width, image.height),
+            None => "image".to_string(),
+        },
+        MsgType::Clear => "cleared".to_string(),
+    }
+}
+
+// actions need org.freedesktop.Notifications, elsewhere `uniclip apply` and the tray accept
+#[cfg(all(unix, not(target_os = "macos")))]
+fn wait_for_accept(handle: notify_rust::NotificationHandle, state: State, id: u32) {
+    handle.wait_for_action(|action| {
+        if action == "accept" {
+            if let Err(err) = crate::apply_staged(&state, Some(id)) {
+                warn!(id, "accept failed: {err:#}");
+            }
+        }
+    });
+}
+
+#[cfg(not(all(unix, not(target_os = "macos"))))]
+fn wait_for_accept<T>(_handle: T, _state: State, _id: u32) {}
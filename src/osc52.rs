@@ -0,0 +1,454 @@
+use crate::{accept, clip::ClipMsg, groups::Mode, publish, stale, State};
+use anyhow::anyhow;
+use portable_pty::{native_pty_system, CommandBuilder, PtySize};
+use std::{
+    io::{Read, Write},
+    sync::{mpsc::Receiver, Arc, Mutex},
+};
+use tracing::{debug, info, info_span};
+use uniclip_proto::clip_msg::MsgType;
+
+const ESC: u8 = 0x1b;
+const BEL: u8 = 0x07;
+// a runaway sequence is passed through instead of buffering forever
+const MAX_SEQUENCE: usize = 16 * 1024 * 1024;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Sequence {
+    Set(Vec<u8>),
+    Query {
+        selection: Vec<u8>,
+        terminator: &'static [u8],
+    },
+}
+
+#[derive(Clone, Copy)]
+enum Parse {
+    Ground,
+    Escape,
+    Osc,
+    OscEscape,
+}
+
+// splits a terminal output stream into bytes to pass through and OSC 52 sequences,
+// sequences may be split across reads
+pub struct Parser {
+    state: Parse,
+    buf: Vec<u8>,
+}
+
+impl Parser {
+    pub fn new() -> Self {
+        Self {
+            state: Parse::Ground,
+            buf: Vec::new(),
+        }
+    }
+
+    // set sequences are passed through as well so an outer terminal still sees them,
+    // queries are not since they are answered here
+    pub fn feed(&mut self, data: &[u8], output: &mut Vec<u8>) -> Vec<Sequence> {
+        let mut sequences = Vec::new();
+        for &byte in data {
+            match self.state {
+                Parse::Ground => {
+                    if byte == ESC {
+                        self.state = Parse::Escape;
+                    } else {
+                        output.push(byte);
+                    }
+                }
+                Parse::Escape => self.escape(byte, output),
+                Parse::Osc => match byte {
+                    BEL => self.finish(b"\x07", output, &mut sequences),
+                    ESC => self.state = Parse::OscEscape,
+                    _ => {
+                        self.buf.push(byte);
+                        if self.buf.len() > MAX_SEQUENCE {
+                            output.extend_from_slice(&[ESC, b']']);
+                            output.append(&mut self.buf);
+                            self.state = Parse::Ground;
+                        }
+                    }
+                },
+                Parse::OscEscape => {
+                    if byte == b'\\' {
+                        self.finish(b"\x1b\\", output, &mut sequences);
+                    } else {
+                        // not a string terminator, the sequence was abandoned
+                        output.extend_from_slice(&[ESC, b']']);
+                        output.append(&mut self.buf);
+                        self.escape(byte, output);
+                    }
+                }
+            }
+        }
+        sequences
+    }
+
+    // the byte after an ESC outside of a sequence
+    fn escape(&mut self, byte: u8, output: &mut Vec<u8>) {
+        match byte {
+            b']' => {
+                self.buf.clear();
+                self.state = Parse::Osc;
+            }
+            ESC => {
+                output.push(ESC);
+                self.state = Parse::Escape;
+            }
+            _ => {
+                output.extend_from_slice(&[ESC, byte]);
+                self.state = Parse::Ground;
+            }
+        }
+    }
+
+    fn finish(
+        &mut self,
+        terminator: &'static [u8],
+        output: &mut Vec<u8>,
+        sequences: &mut Vec<Sequence>,
+    ) {
+        self.state = Parse::Ground;
+        let body = std::mem::take(&mut self.buf);
+
+        let mut fields = body.splitn(3, |&b| b == b';');
+        if let (Some(b"52"), Some(selection), Some(data)) =
+            (fields.next(), fields.next(), fields.next())
+        {
+            if data == b"?" {
+                sequences.push(Sequence::Query {
+                    selection: selection.to_vec(),
+                    terminator,
+                });
+                return;
+            }
+            match base64::decode(data) {
+                Ok(data) => sequences.push(Sequence::Set(data)),
+                Err(err) => debug!("invalid osc 52 payload: {err}"),
+            }
+        }
+
+        output.extend_from_slice(&[ESC, b']']);
+        output.extend_from_slice(&body);
+        output.extend_from_slice(terminator);
+    }
+}
+
+pub fn reply(selection: &[u8], terminator: &[u8], text: &str) -> Vec<u8> {
+    let mut reply = b"\x1b]52;".to_vec();
+    reply.extend_from_slice(selection);
+    reply.push(b';');
+    reply.extend_from_slice(base64::encode(text).as_bytes());
+    reply.extend_from_slice(terminator);
+    reply
+}
+
+// wraps `command` in a pty, or filters stdin to stdout when it is empty
+pub fn run(state: State, from_net_rx: Receiver<uniclip_net::Received>, command: Vec<String>) {
+    {
+        let state = state.clone();
+        std::thread::spawn(move || {
+            let _span = info_span!("apply").entered();
+            for received in from_net_rx {
+                let origin = received.origin;
+                let msg = match accept(&state, received) {
+                    Some(msg) => msg,
+                    None => continue,
+                };
+                if stale(&state, &msg, origin) {
+                    debug!(id = msg.id, "fetched from a mailbox, keep in history only");
+                    state.events.send(&msg);
+                    state.history.push(msg);
+                    continue;
+                }
+                receive(&state, msg);
+            }
+        });
+    }
+
+    let result = if command.is_empty() {
+        filter_stdin(&state)
+    } else {
+        wrap(&state, &command)
+    };
+    match result {
+        Ok(code) => std::process::exit(code),
+        Err(err) => {
+            eprintln!("{err:#}");
+            std::process::exit(1);
+        }
+    }
+}
+
+fn receive(state: &State, msg: uniclip_proto::ClipMsg) {
+    state.events.send(&msg);
+
+    match msg.typ() {
+        MsgType::Text => {
+            info!(id = msg.id, "receive from net: text");
+            state.clip.set_current(msg.id);
+            state.history.push(msg);
+        }
+        // a terminal can only take text
+        MsgType::Image => debug!(id = msg.id, "ignore image from net"),
+        MsgType::Clear => {
+            info!(id = msg.id, "receive from net: clear");
+            state.history.remove(msg.id);
+        }
+    }
+}
+
+// publish keeps it in the history, where queries find it
+fn set(state: &State, data: Vec<u8>) {
+    let text = String::from_utf8_lossy(&data).into_owned();
+    if let Ok(id) = publish(state, ClipMsg::Text(text), Mode::Auto) {
+        state.clip.set_current(id);
+    }
+}
+
+// the newest text of the history, which also holds items fetched from mailboxes
+fn latest_text(state: &State) -> String {
+    state
+        .history
+        .entries()
+        .into_iter()
+        .rev()
+        .find(|msg| msg.typ() == MsgType::Text)
+        .map(|msg| msg.text().to_string())
+        .unwrap_or_default()
+}
+
+fn filter_stdin(state: &State) -> anyhow::Result<i32> {
+    let mut stdin = std::io::stdin().lock();
+    let mut stdout = std::io::stdout().lock();
+    let mut parser = Parser::new();
+    let mut buf = [0; 8192];
+    let mut output = Vec::new();
+
+    loop {
+        let n = stdin.read(&mut buf)?;
+        if n == 0 {
+            return Ok(0);
+        }
+        for sequence in parser.feed(&buf[..n], &mut output) {
+            match sequence {
+                Sequence::Set(data) => set(state, data),
+                Sequence::Query { .. } => debug!("no terminal to answer osc 52 query"),
+            }
+        }
+        stdout.write_all(&output)?;
+        stdout.flush()?;
+        output.clear();
+    }
+}
+
+// restores the outer terminal on every exit path
+struct RawMode;
+
+impl RawMode {
+    fn enable() -> anyhow::Result<Self> {
+        crossterm::terminal::enable_raw_mode()?;
+        Ok(Self)
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        let _ = crossterm::terminal::disable_raw_mode();
+    }
+}
+
+fn wrap(state: &State, command: &[String]) -> anyhow::Result<i32> {
+    let (cols, rows) = crossterm::terminal::size().unwrap_or((80, 24));
+    let pair = native_pty_system()
+        .openpty(PtySize {
+            rows,
+            cols,
+            pixel_width: 0,
+            pixel_height: 0,
+        })
+        .map_err(|err| anyhow!("open pty failed: {err}"))?;
+
+    let mut builder = CommandBuilder::new(&command[0]);
+    builder.args(&command[1..]);
+    if let Ok(dir) = std::env::current_dir() {
+        builder.cwd(dir);
+    }
+    let mut child = pair
+        .slave
+        .spawn_command(builder)
+        .map_err(|err| anyhow!("spawn {} failed: {err}", command[0]))?;
+    drop(pair.slave);
+
+    let mut reader = pair
+        .master
+        .try_clone_reader()
+        .map_err(|err| anyhow!("read pty failed: {err}"))?;
+    let writer = Arc::new(Mutex::new(
+        pair.master
+            .take_writer()
+            .map_err(|err| anyhow!("write pty failed: {err}"))?,
+    ));
+
+    let raw_mode = RawMode::enable()?;
+
+    {
+        let writer = writer.clone();
+        std::thread::spawn(move || {
+            let mut stdin = std::io::stdin();
+            let mut buf = [0; 8192];
+            while let Ok(n) = stdin.read(&mut buf) {
+                if n == 0 || writer.lock().unwrap().write_all(&buf[..n]).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+
+    let mut stdout = std::io::stdout();
+    let mut parser = Parser::new();
+    let mut buf = [0; 8192];
+    let mut output = Vec::new();
+    // the pty reports an error instead of eof on some platforms once the child exits
+    while let Ok(n) = reader.read(&mut buf) {
+        if n == 0 {
+            break;
+        }
+        for sequence in parser.feed(&buf[..n], &mut output) {
+            match sequence {
+                Sequence::Set(data) => set(state, data),
+                Sequence::Query {
+                    selection,
+                    terminator,
+                } => {
+                    let reply = reply(&selection, terminator, &latest_text(state));
+                    let mut writer = writer.lock().unwrap();
+                    if let Err(err) = writer.write_all(&reply).and_then(|_| writer.flush()) {
+                        debug!("answer osc 52 query failed: {err}");
+                    }
+                }
+            }
+        }
+        stdout.write_all(&output)?;
+        stdout.flush()?;
+        output.clear();
+    }
+
+    let status = child.wait()?;
+    drop(raw_mode);
+    Ok(status.exit_code() as i32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query(terminator: &'static [u8]) -> Sequence {
+        Sequence::Query {
+            selection: b"c".to_vec(),
+            terminator,
+        }
+    }
+
+    // input, passed through output, sequences
+    fn cases() -> Vec<(&'static str, &'static str, Vec<Sequence>)> {
+        vec![
+            ("plain text", "plain text", vec![]),
+            ("\x1b[31mred\x1b[0m", "\x1b[31mred\x1b[0m", vec![]),
+            (
+                "a\x1b]52;c;aGk=\x07b",
+                "a\x1b]52;c;aGk=\x07b",
+                vec![Sequence::Set(b"hi".to_vec())],
+            ),
+            (
+                "a\x1b]52;c;aGk=\x1b\\b",
+                "a\x1b]52;c;aGk=\x1b\\b",
+                vec![Sequence::Set(b"hi".to_vec())],
+            ),
+            ("x\x1b]52;c;?\x07y", "xy", vec![query(b"\x07")]),
+            ("x\x1b]52;c;?\x1b\\y", "xy", vec![query(b"\x1b\\")]),
+            // window title
+            ("\x1b]0;title\x07", "\x1b]0;title\x07", vec![]),
+            ("\x1b]52;c;!!!\x07", "\x1b]52;c;!!!\x07", vec![]),
+            // abandoned by another escape sequence
+            ("\x1b]52;c\x1b[1m", "\x1b]52;c\x1b[1m", vec![]),
+            (
+                "\x1b\x1b]52;c;aGk=\x07",
+                "\x1b\x1b]52;c;aGk=\x07",
+                vec![Sequence::Set(b"hi".to_vec())],
+            ),
+        ]
+    }
+
+    #[test]
+    fn feed_whole() {
+        for (input, expected_output, expected_sequences) in cases() {
+            let (input, expected_output) = (input.as_bytes(), expected_output.as_bytes());
+            let mut output = Vec::new();
+            let sequences = Parser::new().feed(input, &mut output);
+            assert_eq!(
+                output,
+                expected_output,
+                "{:?}",
+                String::from_utf8_lossy(input)
+            );
+            assert_eq!(
+                sequences,
+                expected_sequences,
+                "{:?}",
+                String::from_utf8_lossy(input)
+            );
+        }
+    }
+
+    #[test]
+    fn feed_split_at_every_byte() {
+        for (input, expected_output, expected_sequences) in cases() {
+            let (input, expected_output) = (input.as_bytes(), expected_output.as_bytes());
+            for split in 0..=input.len() {
+                let (first, second) = input.split_at(split);
+                let mut parser = Parser::new();
+                let mut output = Vec::new();
+                let mut sequences = parser.feed(first, &mut output);
+                sequences.extend(parser.feed(second, &mut output));
+                assert_eq!(
+                    (output.as_slice(), sequences.as_slice()),
+                    (expected_output, expected_sequences.as_slice()),
+                    "{:?} split at {split}",
+                    String::from_utf8_lossy(input)
+                );
+            }
+
+            let mut parser = Parser::new();
+            let mut output = Vec::new();
+            let sequences: Vec<_> = input
+                .iter()
+                .flat_map(|byte| parser.feed(&[*byte], &mut output))
+                .collect();
+            assert_eq!(output, expected_output);
+            assert_eq!(sequences, expected_sequences);
+        }
+    }
+
+    #[test]
+    fn queries_are_answered_by_reply() {
+        let mut output = Vec::new();
+        let sequences = Parser::new().feed(b"\x1b]52;c;?\x1b\\", &mut output);
+        assert!(output.is_empty());
+        let answer = match sequences.as_slice() {
+            [Sequence::Query {
+                selection,
+                terminator,
+            }] => reply(selection, terminator, "hi"),
+            other => panic!("expected a query, got {other:?}"),
+        };
+        assert_eq!(answer, b"\x1b]52;c;aGk=\x1b\\");
+
+        // the answer is a set sequence of its own
+        let mut output = Vec::new();
+        let sequences = Parser::new().feed(&answer, &mut output);
+        assert_eq!(sequences, vec![Sequence::Set(b"hi".to_vec())]);
+    }
+}
@@ -0,0 +1,96 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Condvar, Mutex},
+};
+use tokio::sync::mpsc::Sender;
+use tracing::{debug, error};
+use uniclip_net::Command;
+
+/// Hands local items to the network thread without blocking the clipboard. While the
+/// network is busy only the latest item waits, older ones are stale and dropped.
+/// Control messages such as clears are queued in order and never dropped.
+pub struct Outbox {
+    pending: Mutex<Pending>,
+    ready: Condvar,
+}
+
+enum Entry {
+    // the commands of one item
+    Item(Vec<Command>),
+    Control(Command),
+}
+
+#[derive(Default)]
+struct Pending {
+    queue: VecDeque<Entry>,
+    dropped: u64,
+    stopped: bool,
+}
+
+impl Outbox {
+    pub fn spawn(to_net_tx: Sender<Command>) -> Arc<Self> {
+        let outbox = Arc::new(Self {
+            pending: Mutex::new(Pending::default()),
+            ready: Condvar::new(),
+        });
+        let forward = outbox.clone();
+        std::thread::spawn(move || forward.forward(to_net_tx));
+        outbox
+    }
+
+    /// Replaces the item still waiting, if any.
+    pub fn publish(&self, commands: Vec<Command>) -> Result<(), &'static str> {
+        let mut pending = self.pending.lock().unwrap();
+        if pending.stopped {
+            return Err("network stopped");
+        }
+        let waiting = pending.queue.len();
+        pending
+            .queue
+            .retain(|entry| matches!(entry, Entry::Control(_)));
+        let dropped = (waiting - pending.queue.len()) as u64;
+        if dropped > 0 {
+            pending.dropped += dropped;
+            debug!(dropped = pending.dropped, "drop stale pending item");
+        }
+        pending.queue.push_back(Entry::Item(commands));
+        self.ready.notify_one();
+        Ok(())
+    }
+
+    /// Queues `command` behind what is waiting.
+    pub fn control(&self, command: Command) -> Result<(), &'static str> {
+        let mut pending = self.pending.lock().unwrap();
+        if pending.stopped {
+            return Err("network stopped");
+        }
+        pending.queue.push_back(Entry::Control(command));
+        self.ready.notify_one();
+        Ok(())
+    }
+
+    fn forward(&self, to_net_tx: Sender<Command>) {
+        loop {
+            let entry = {
+                let mut pending = self.pending.lock().unwrap();
+                loop {
+                    match pending.queue.pop_front() {
+                        Some(entry) => break entry,
+                        None => pending = self.ready.wait(pending).unwrap(),
+                    }
+                }
+            };
+            let commands = match entry {
+                Entry::Item(commands) => commands,
+                Entry::Control(command) => vec![command],
+            };
+            for command in commands {
+                if to_net_tx.blocking_send(command).is_err() {
+                    error!("network stopped, local items stay local");
+                    self.pending.lock().unwrap().stopped = true;
+                    return;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,543 @@
+use crate::{
+    filter::{Filter, FilterConfig},
+    groups::{Direction, GroupSettings, Mode, RouteSettings, DEFAULT_GROUP},
+    logging::LogConfig,
+    notification::NotificationConfig,
+    transform::{Kind, TransformConfig},
+};
+use anyhow::{anyhow, bail, Context};
+use directories::ProjectDirs;
+use rand::{distributions::Alphanumeric, Rng};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashSet,
+    fs,
+    io::Write,
+    iter,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tracing_subscriber::EnvFilter;
+use uniclip_net::{limits::Limits, mailbox::MailboxConfig, GroupConfig, Multiaddr};
+use uniclip_proto::{clip_msg::MsgType, ClipMsg};
+
+pub const FILE_NAME: &str = "settings.toml";
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings {
+    // the "default" group
+    #[serde(alias = "domain")]
+    pub topic: String,
+    pub secret: Option<String>,
+    pub direction: Direction,
+    pub publish: Mode,
+    pub apply: Mode,
+    // shown on the other devices, the host name by default
+    pub device_name: String,
+    // shares the current clipboard, e.g. "CTRL+SHIFT+U"
+    pub hotkey: Option<String>,
+
+    pub relays: Vec<String>,
+    pub listen_addrs: Vec<String>,
+    pub data_dir: Option<PathBuf>,
+
+    pub poll_interval_ms: u64,
+    // seconds after which receivers clear synced items
+    pub ttl: Option<u32>,
+
+    // more groups, each with its own topic and secret
+    pub groups: Vec<GroupSettings>,
+    // which groups local copies go to, the first matching route wins
+    pub routes: Vec<RouteSettings>,
+    // rewrites of text items in their order, per direction and group
+    pub transforms: Vec<TransformConfig>,
+
+    pub limits: Limits,
+    pub formats: Formats,
+    pub filter: FilterConfig,
+    pub history: HistoryConfig,
+    pub mailbox: MailboxSettings,
+    pub api: ApiSettings,
+    pub notifications: NotificationConfig,
+    pub log: LogConfig,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        let net = uniclip_net::Config::default();
+        Self {
+            topic: String::new(),
+            secret: None,
+            direction: Direction::default(),
+            publish: Mode::default(),
+            apply: Mode::default(),
+            device_name: gethostname::gethostname().to_string_lossy().into_owned(),
+            hotkey: None,
+
+            relays: net.relays.iter().map(ToString::to_string).collect(),
+            listen_addrs: net.listen_addrs.iter().map(ToString::to_string).collect(),
+            data_dir: None,
+
+            poll_interval_ms: 200,
+            ttl: None,
+
+            groups: Vec::new(),
+            routes: Vec::new(),
+            transforms: Vec::new(),
+
+            limits: Limits::default(),
+            formats: Formats::default(),
+            filter: FilterConfig::default(),
+            history: HistoryConfig::default(),
+            mailbox: MailboxSettings::default(),
+            api: ApiSettings::default(),
+            notifications: NotificationConfig::default(),
+            log: LogConfig::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Formats {
+    pub text: bool,
+    pub image: bool,
+}
+
+impl Default for Formats {
+    fn default() -> Self {
+        Self {
+            text: true,
+            image: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HistoryConfig {
+    pub size: usize,
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        Self { size: 50 }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MailboxSettings {
+    // keep items for peers that are offline, for always-on machines
+    pub serve: bool,
+    pub size: usize,
+    pub ttl_secs: u64,
+}
+
+impl Default for MailboxSettings {
+    fn default() -> Self {
+        let config = MailboxConfig::default();
+        Self {
+            serve: false,
+            size: config.size,
+            ttl_secs: config.ttl.as_secs(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ApiSettings {
+    // local http api, see README
+    pub enabled: bool,
+    pub listen: String,
+    // sent as "Authorization: Bearer <token>"
+    pub token: Option<String>,
+}
+
+impl Default for ApiSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            listen: "127.0.0.1:34570".to_string(),
+            token: None,
+        }
+    }
+}
+
+fn project_dirs() -> anyhow::Result<ProjectDirs> {
+    ProjectDirs::from("com", "zu1k", "uniclip").ok_or_else(|| anyhow!("no home directory found"))
+}
+
+pub fn default_path() -> anyhow::Result<PathBuf> {
+    Ok(project_dirs()?.config_dir().join(FILE_NAME))
+}
+
+// only variables naming a known key, any other UNICLIP_* variable would fail
+// deny_unknown_fields
+fn environment() -> config::Environment {
+    let known = serde_json::to_value(Settings::default()).unwrap_or_default();
+    let variables = std::env::vars()
+        .filter(|(name, _)| known_key(&known, name))
+        .collect();
+    config::Environment::with_prefix("UNICLIP")
+        .separator("__")
+        .try_parsing(true)
+        .source(Some(variables))
+}
+
+// e.g. UNICLIP_API__TOKEN for api.token
+fn known_key(known: &serde_json::Value, name: &str) -> bool {
+    let name = name.to_lowercase();
+    let path = match name.strip_prefix("uniclip") {
+        Some(path) if path.starts_with('_') => path.trim_start_matches('_'),
+        _ => return false,
+    };
+    let mut value = known;
+    for key in path.split("__") {
+        match value.get(key) {
+            Some(next) => value = next,
+            None => return false,
+        }
+    }
+    true
+}
+
+impl Settings {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let settings = config::Config::builder()
+            .add_source(config::File::from(path))
+            .add_source(environment())
+            .build()
+            .with_context(|| format!("failed to read {}", path.display()))?
+            .try_deserialize::<Settings>()
+            .with_context(|| format!("invalid settings in {}", path.display()))?;
+
+        settings.validate()?;
+        Ok(settings)
+    }
+
+    pub fn generate() -> Self {
+        let random = |len| {
+            rand::thread_rng()
+                .sample_iter(&Alphanumeric)
+                .take(len)
+                .map(char::from)
+                .collect::<String>()
+        };
+
+        Self {
+            topic: format!("uniclip-{}", random(12).to_lowercase()),
+            secret: Some(random(32)),
+            api: ApiSettings {
+                token: Some(random(32)),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    pub fn init(path: &Path, force: bool) -> anyhow::Result<()> {
+        if path.exists() && !force {
+            bail!(
+                "{} already exists, use --force to overwrite",
+                path.display()
+            );
+        }
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let content = toml::to_string_pretty(&Self::generate())?;
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        // the secret and the api token are for the user only
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+            options.mode(0o600);
+            let file = options.open(path)?;
+            // mode only applies to new files, --force may overwrite an existing one
+            file.set_permissions(fs::Permissions::from_mode(0o600))?;
+            (&file).write_all(content.as_bytes())?;
+        }
+        #[cfg(not(unix))]
+        options.open(path)?.write_all(content.as_bytes())?;
+        Ok(())
+    }
+
+    /// Why the file at `path` exposes the secrets, if it does.
+    pub fn permission_warning(path: &Path) -> Option<String> {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(path).ok()?.permissions().mode();
+            (mode & 0o077 != 0).then(|| {
+                format!(
+                    "{} is readable by other users (mode {:o}), restrict it with chmod 600",
+                    path.display(),
+                    mode & 0o777
+                )
+            })
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = path;
+            None
+        }
+    }
+
+    pub fn validate(&self) -> anyhow::Result<()> {
+        let mut errors = Vec::new();
+
+        if self.topic.trim().is_empty() {
+            errors.push("topic: must not be empty".to_string());
+        }
+        if let Some(secret) = &self.secret {
+            if secret.len() < 8 {
+                errors.push("secret: must be at least 8 characters".to_string());
+            }
+        }
+        let mut names = HashSet::from([DEFAULT_GROUP]);
+        let mut topics = HashSet::from([self.topic.as_str()]);
+        for (idx, group) in self.groups.iter().enumerate() {
+            if group.name.trim().is_empty() {
+                errors.push(format!("groups[{idx}].name: must not be empty"));
+            } else if !names.insert(group.name.as_str()) {
+                errors.push(format!("groups[{idx}].name: {:?} is taken", group.name));
+            }
+            if group.topic.trim().is_empty() {
+                errors.push(format!("groups[{idx}].topic: must not be empty"));
+            } else if !topics.insert(group.topic.as_str()) {
+                errors.push(format!("groups[{idx}].topic: {:?} is taken", group.topic));
+            }
+            if matches!(&group.secret, Some(secret) if secret.len() < 8) {
+                errors.push(format!(
+                    "groups[{idx}].secret: must be at least 8 characters"
+                ));
+            }
+            if let Err(err) = Filter::new(&group.filter) {
+                errors.push(format!("groups[{idx}].filter.patterns: {err}"));
+            }
+        }
+        for (idx, route) in self.routes.iter().enumerate() {
+            if let Some(Err(err)) = route.pattern.as_deref().map(regex::Regex::new) {
+                errors.push(format!("routes[{idx}].pattern: {err}"));
+            }
+            for name in &route.groups {
+                let direction = match name.as_str() {
+                    DEFAULT_GROUP => Some(self.direction),
+                    _ => self
+                        .groups
+                        .iter()
+                        .find(|group| &group.name == name)
+                        .map(|group| group.direction),
+                };
+                match direction {
+                    None => errors.push(format!("routes[{idx}].groups: no group {name:?}")),
+                    Some(direction) if !direction.sends() => {
+                        errors.push(format!("routes[{idx}].groups: {name:?} only receives"))
+                    }
+                    Some(_) => {}
+                }
+            }
+        }
+        for (idx, transform) in self.transforms.iter().enumerate() {
+            for name in &transform.groups {
+                if name != DEFAULT_GROUP && !self.groups.iter().any(|group| &group.name == name) {
+                    errors.push(format!("transforms[{idx}].groups: no group {name:?}"));
+                }
+            }
+            match transform.transform {
+                Kind::Command if transform.command.is_empty() => {
+                    errors.push(format!("transforms[{idx}].command: must not be empty"))
+                }
+                Kind::Command if transform.timeout_ms == 0 => {
+                    errors.push(format!("transforms[{idx}].timeout_ms: must be positive"))
+                }
+                Kind::Command => {}
+                _ if !transform.command.is_empty() => errors.push(format!(
+                    "transforms[{idx}].command: only for transform = \"command\""
+                )),
+                _ => {}
+            }
+        }
+        if let Some(hotkey) = &self.hotkey {
+            if let Err(err) = tauri_hotkey::parse_hotkey(hotkey) {
+                errors.push(format!("hotkey: {hotkey:?}: {err:?}"));
+            }
+        }
+        if self.device_name.trim().is_empty() {
+            errors.push("device_name: must not be empty".to_string());
+        } else if self.device_name.len() > 64 {
+            errors.push("device_name: must be at most 64 bytes".to_string());
+        }
+
+        for relay in &self.relays {
+            match relay.parse::<Multiaddr>() {
+                Ok(address) if uniclip_net::relay_peer_id(&address).is_none() => {
+                    errors.push(format!("relays: {relay:?} must end with /p2p/<peer id>"))
+                }
+                Ok(_) => {}
+                Err(err) => errors.push(format!("relays: {relay:?}: {err}")),
+            }
+        }
+        for address in &self.listen_addrs {
+            if let Err(err) = address.parse::<Multiaddr>() {
+                errors.push(format!("listen_addrs: {address:?}: {err}"));
+            }
+        }
+
+        if self.poll_interval_ms == 0 {
+            errors.push("poll_interval_ms: must be greater than 0".to_string());
+        }
+        if self.ttl == Some(0) {
+            errors.push("ttl: must be greater than 0".to_string());
+        }
+        if self.limits.text == 0 {
+            errors.push("limits.text: must be greater than 0".to_string());
+        }
+        if self.limits.image == 0 {
+            errors.push("limits.image: must be greater than 0".to_string());
+        }
+        for (name, limit) in [("text", self.limits.text), ("image", self.limits.image)] {
+            if uniclip_net::max_message_size(limit) > uniclip_net::MAX_MESSAGE_SIZE {
+                errors.push(format!(
+                    "limits.{name}: at most {} bytes fit in a message",
+                    uniclip_net::MAX_MESSAGE_SIZE - uniclip_net::max_message_size(0)
+                ));
+            }
+        }
+        if self.mailbox.serve && self.mailbox.size == 0 {
+            errors.push("mailbox.size: must be greater than 0".to_string());
+        }
+        if self.api.enabled {
+            // the token is sent in plain http
+            match self.api.listen.parse::<SocketAddr>() {
+                Ok(addr) if !addr.ip().is_loopback() => errors.push(format!(
+                    "api.listen: {:?} must be a loopback address",
+                    self.api.listen
+                )),
+                Ok(_) => {}
+                Err(err) => errors.push(format!("api.listen: {:?}: {err}", self.api.listen)),
+            }
+            match &self.api.token {
+                Some(token) if token.len() >= 16 => {}
+                _ => errors.push("api.token: at least 16 characters are required".to_string()),
+            }
+        }
+        if !self.formats.text && !self.formats.image {
+            errors.push("formats: at least one format must be enabled".to_string());
+        }
+        if let Err(err) = Filter::new(&self.filter) {
+            errors.push(format!("filter.patterns: {err}"));
+        }
+        if let Err(err) = EnvFilter::try_new(&self.log.level) {
+            errors.push(format!("log.level: {err}"));
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(anyhow!("invalid settings:\n  {}", errors.join("\n  ")))
+        }
+    }
+
+    // settings that are only read at startup
+    pub fn restart_required(&self, new: &Settings) -> Vec<&'static str> {
+        let mut changed = Vec::new();
+        if self.device_name != new.device_name {
+            changed.push("device_name");
+        }
+        if self.hotkey != new.hotkey {
+            changed.push("hotkey");
+        }
+        if self.listen_addrs != new.listen_addrs {
+            changed.push("listen_addrs");
+        }
+        if self.data_dir != new.data_dir {
+            changed.push("data_dir");
+        }
+        // the network keeps the message size it started with, smaller limits apply live
+        if new.limits.largest() > self.limits.largest() {
+            changed.push("limits");
+        }
+        if self.history.size != new.history.size {
+            changed.push("history.size");
+        }
+        if self.mailbox.serve != new.mailbox.serve
+            || self.mailbox.size != new.mailbox.size
+            || self.mailbox.ttl_secs != new.mailbox.ttl_secs
+        {
+            changed.push("mailbox");
+        }
+        if self.api.enabled != new.api.enabled || self.api.listen != new.api.listen {
+            changed.push("api");
+        }
+        if self.log.level != new.log.level
+            || self.log.file != new.log.file
+            || self.log.content != new.log.content
+        {
+            changed.push("log");
+        }
+        changed
+    }
+
+    pub fn data_dir(&self) -> anyhow::Result<PathBuf> {
+        match &self.data_dir {
+            Some(dir) => Ok(dir.clone()),
+            None => Ok(project_dirs()?.data_dir().to_path_buf()),
+        }
+    }
+
+    pub fn accepts(&self, msg: &ClipMsg) -> Result<(), &'static str> {
+        match msg.typ() {
+            MsgType::Text if !self.formats.text => Err("text format disabled"),
+            MsgType::Image if !self.formats.image => Err("image format disabled"),
+            _ => self.limits.accepts(msg),
+        }
+    }
+
+    pub fn poll_interval(&self) -> Duration {
+        Duration::from_millis(self.poll_interval_ms)
+    }
+
+    // the default group first
+    pub fn group_configs(&self) -> Vec<GroupConfig> {
+        iter::once(GroupConfig {
+            topic: self.topic.clone(),
+            secret: self.secret.clone(),
+        })
+        .chain(self.groups.iter().map(|group| GroupConfig {
+            topic: group.topic.clone(),
+            secret: group.secret.clone(),
+        }))
+        .collect()
+    }
+
+    pub fn net_config(&self) -> anyhow::Result<uniclip_net::Config> {
+        Ok(uniclip_net::Config {
+            dir: self.data_dir()?,
+            groups: self.group_configs(),
+
+            listen_addrs: self
+                .listen_addrs
+                .iter()
+                .map(|address| address.parse::<Multiaddr>())
+                .collect::<Result<_, _>>()?,
+            relays: self
+                .relays
+                .iter()
+                .map(|address| address.parse::<Multiaddr>())
+                .collect::<Result<_, _>>()?,
+            mailbox: self.mailbox.serve.then(|| MailboxConfig {
+                size: self.mailbox.size,
+                ttl: Duration::from_secs(self.mailbox.ttl_secs),
+                ..Default::default()
+            }),
+            device_name: self.device_name.trim().to_string(),
+            max_message_size: uniclip_net::max_message_size(self.limits.largest()),
+        })
+    }
+}
@@ -0,0 +1,216 @@
+use crate::groups::Direction;
+use regex::{Captures, Regex};
+use serde::{Deserialize, Serialize};
+use std::{
+    io::{Read, Write},
+    process::{Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+use tracing::{debug, warn};
+use uniclip_proto::{clip_msg::MsgType, ClipMsg};
+
+// html2text wraps lines, wide enough that it never does
+const HTML_WIDTH: usize = 10_000;
+
+// query parameters that only identify where a link was shared
+const TRACKING_PARAMS: &[&str] = &[
+    "fbclid", "gclid", "dclid", "msclkid", "mc_cid", "mc_eid", "igshid", "yclid", "_hsenc",
+    "_hsmi", "ref_src",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Kind {
+    StripTracking,
+    CrlfToLf,
+    TrimTrailingWhitespace,
+    HtmlToText,
+    // text on stdin, the replacement on stdout
+    Command,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct TransformConfig {
+    pub transform: Kind,
+    // "send" for local copies going out, "receive" for items coming in
+    #[serde(default)]
+    pub direction: Direction,
+    // group names, all groups when empty
+    #[serde(default)]
+    pub groups: Vec<String>,
+    // program and arguments of a `command` transform
+    #[serde(default)]
+    pub command: Vec<String>,
+    #[serde(default = "default_timeout_ms")]
+    pub timeout_ms: u64,
+}
+
+fn default_timeout_ms() -> u64 {
+    2000
+}
+
+/// The transforms of the settings in their order, text items only.
+#[derive(Clone)]
+pub struct Transforms {
+    configs: Vec<TransformConfig>,
+    url: Regex,
+}
+
+impl Transforms {
+    pub fn new(configs: &[TransformConfig]) -> Self {
+        Self {
+            configs: configs.to_vec(),
+            url: Regex::new(r#"https?://[^\s<>"']+"#).unwrap(),
+        }
+    }
+
+    /// Rewrites an item going out when `outgoing`, coming in otherwise. `group` is None
+    /// for items sent to a single peer, only transforms for all groups apply to those.
+    pub fn apply(&self, msg: &mut ClipMsg, group: Option<&str>, outgoing: bool) {
+        if msg.typ() != MsgType::Text {
+            return;
+        }
+        for config in &self.configs {
+            let direction = match outgoing {
+                true => config.direction.sends(),
+                false => config.direction.receives(),
+            };
+            let group = match group {
+                Some(group) => {
+                    config.groups.is_empty() || config.groups.iter().any(|name| name == group)
+                }
+                None => config.groups.is_empty(),
+            };
+            if !direction || !group {
+                continue;
+            }
+
+            let text = msg.text();
+            let transformed = match config.transform {
+                Kind::StripTracking => Some(self.strip_tracking(text)),
+                Kind::CrlfToLf => Some(text.replace("\r\n", "\n")),
+                Kind::TrimTrailingWhitespace => Some(trim_trailing_whitespace(text)),
+                Kind::HtmlToText => html_to_text(text),
+                Kind::Command => run_command(&config.command, config.timeout_ms, text),
+            };
+            if let Some(transformed) = transformed {
+                if transformed != text {
+                    debug!(id = msg.id, transform = ?config.transform, "transformed clip");
+                    msg.text = Some(transformed);
+                }
+            }
+        }
+    }
+
+    fn strip_tracking(&self, text: &str) -> String {
+        self.url
+            .replace_all(text, |captures: &Captures| {
+                let link = &captures[0];
+                let mut url = match url::Url::parse(link) {
+                    Ok(url) => url,
+                    Err(_) => return link.to_string(),
+                };
+                let tracking =
+                    |key: &str| key.starts_with("utm_") || TRACKING_PARAMS.contains(&key);
+                if !url.query_pairs().any(|(key, _)| tracking(&key)) {
+                    return link.to_string();
+                }
+                let kept: Vec<(String, String)> = url
+                    .query_pairs()
+                    .filter(|(key, _)| !tracking(key))
+                    .map(|(key, value)| (key.into_owned(), value.into_owned()))
+                    .collect();
+                if kept.is_empty() {
+                    url.set_query(None);
+                } else {
+                    url.query_pairs_mut().clear().extend_pairs(kept);
+                }
+                url.to_string()
+            })
+            .into_owned()
+    }
+}
+
+fn trim_trailing_whitespace(text: &str) -> String {
+    let mut trimmed: String = text
+        .lines()
+        .map(str::trim_end)
+        .collect::<Vec<_>>()
+        .join("\n");
+    if text.ends_with('\n') {
+        trimmed.push('\n');
+    }
+    trimmed
+}
+
+// only what looks like markup, copies from browsers usually come as plain text already
+fn html_to_text(text: &str) -> Option<String> {
+    let trimmed = text.trim();
+    if !(trimmed.starts_with('<') && trimmed.ends_with('>')) {
+        return None;
+    }
+    Some(
+        html2text::from_read(trimmed.as_bytes(), HTML_WIDTH)
+            .trim_end()
+            .to_string(),
+    )
+}
+
+// the item stays as it is when the command fails
+fn run_command(command: &[String], timeout_ms: u64, text: &str) -> Option<String> {
+    let (program, args) = command.split_first()?;
+    let mut child = match Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+    {
+        Ok(child) => child,
+        Err(err) => {
+            warn!(%program, "transform command failed to start: {err}");
+            return None;
+        }
+    };
+
+    // both pipes at once, a command may only read all of stdin after writing some output
+    let mut stdin = child.stdin.take()?;
+    let input = text.to_string();
+    thread::spawn(move || stdin.write_all(input.as_bytes()));
+    let mut stdout = child.stdout.take()?;
+    let output = thread::spawn(move || {
+        let mut output = String::new();
+        stdout.read_to_string(&mut output).map(|_| output)
+    });
+
+    let deadline = Instant::now() + Duration::from_millis(timeout_ms);
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break status,
+            Ok(None) if Instant::now() < deadline => thread::sleep(Duration::from_millis(10)),
+            Ok(None) => {
+                warn!(%program, timeout_ms, "transform command timed out");
+                let _ = child.kill();
+                let _ = child.wait();
+                return None;
+            }
+            Err(err) => {
+                warn!(%program, "transform command failed: {err}");
+                return None;
+            }
+        }
+    };
+    if !status.success() {
+        warn!(%program, %status, "transform command failed");
+        return None;
+    }
+    match output.join() {
+        Ok(Ok(output)) => Some(output),
+        _ => {
+            warn!(%program, "transform command wrote no text");
+            None
+        }
+    }
+}
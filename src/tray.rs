@@ -1,17 +1,39 @@
-use tray_item::TrayItem;
+use crate::ipc::{Control, Request};
+use std::sync::mpsc::Sender;
 
 cfg_if::cfg_if! {
     if #[cfg(windows)] {
+        use tray_item::TrayItem;
         use std::{process, sync::mpsc};
+        use tracing::info;
         enum Message {
             Quit,
         }
-        pub fn start_tray() {
+        // tray-item has no submenus, sending to a device is left to `uniclip send`
+        pub fn start_tray(control_tx: Sender<Request>, _state: crate::State) {
             let mut tray = TrayItem::new("Unified Clipboard", "icon").unwrap();
             tray.add_label("Unified Clipboard").unwrap();
+            {
+                let control_tx = control_tx.clone();
+                tray.add_menu_item("Share current clipboard", move || {
+                    control_tx.send(Request::new(Control::Share)).unwrap();
+                })
+                .unwrap();
+            }
+            {
+                let control_tx = control_tx.clone();
+                tray.add_menu_item("Apply staged item", move || {
+                    control_tx.send(Request::new(Control::ApplyStaged)).unwrap();
+                })
+                .unwrap();
+            }
+            tray.add_menu_item("Clear everywhere", move || {
+                control_tx.send(Request::new(Control::ClearEverywhere)).unwrap();
+            })
+            .unwrap();
             let (tx, rx) = mpsc::channel();
             tray.add_menu_item("Quit", move || {
-                println!("Quit");
+                info!("Quit");
                 tx.send(Message::Quit).unwrap();
             })
             .unwrap();
@@ -26,20 +48,150 @@ cfg_if::cfg_if! {
         }
 
     } else if #[cfg(target_os = "linux")] {
-        pub fn start_tray() {
+        use crate::{ago, peers, State};
+        use gtk::{glib, prelude::*};
+        use libappindicator::{AppIndicator, AppIndicatorStatus};
+        use std::{thread, time::Duration};
+
+        const REFRESH: Duration = Duration::from_secs(5);
+
+        pub fn start_tray(control_tx: Sender<Request>, state: State) {
             gtk::init().unwrap();
-            let mut tray = TrayItem::new("Unified Clipboard", "accessories-calculator").unwrap();
-            tray.add_label("Unified Clipboard").unwrap();
-            tray.add_menu_item("Quit", || {
-                gtk::main_quit();
-            }).unwrap();
+            let mut indicator = AppIndicator::new("Unified Clipboard", "accessories-calculator");
+            indicator.set_status(AppIndicatorStatus::Active);
+
+            let mut menu = gtk::Menu::new();
+            let label = gtk::MenuItem::with_label("Unified Clipboard");
+            label.set_sensitive(false);
+            menu.append(&label);
+
+            let share = gtk::MenuItem::with_label("Share current clipboard");
+            {
+                let control_tx = control_tx.clone();
+                share.connect_activate(move |_| {
+                    control_tx.send(Request::new(Control::Share)).unwrap();
+                });
+            }
+            menu.append(&share);
+
+            let apply = gtk::MenuItem::with_label("Apply staged item");
+            {
+                let control_tx = control_tx.clone();
+                apply.connect_activate(move |_| {
+                    control_tx.send(Request::new(Control::ApplyStaged)).unwrap();
+                });
+            }
+            apply.set_sensitive(false);
+            menu.append(&apply);
+
+            let clear = gtk::MenuItem::with_label("Clear everywhere");
+            {
+                let control_tx = control_tx.clone();
+                clear.connect_activate(move |_| {
+                    control_tx.send(Request::new(Control::ClearEverywhere)).unwrap();
+                });
+            }
+            menu.append(&clear);
+
+            let send = gtk::MenuItem::with_label("Send current clipboard to");
+            let devices = gtk::Menu::new();
+            send.set_submenu(Some(&devices));
+            send.set_sensitive(false);
+            menu.append(&send);
+
+            // every device heard from, with when it was last seen
+            let presence = gtk::MenuItem::with_label("Devices");
+            let known = gtk::Menu::new();
+            presence.set_submenu(Some(&known));
+            presence.set_sensitive(false);
+            menu.append(&presence);
+
+            let quit = gtk::MenuItem::with_label("Quit");
+            quit.connect_activate(|_| gtk::main_quit());
+            menu.append(&quit);
+
+            menu.show_all();
+            indicator.set_menu(&mut menu);
+
+            // the net is asked off the gtk thread, the menu is rebuilt on it
+            let (peers_tx, peers_rx) = glib::MainContext::channel(glib::PRIORITY_DEFAULT);
+            thread::spawn(move || loop {
+                let staged = state.staged.lock().unwrap().is_some();
+                if peers_tx.send((peers(&state).unwrap_or_default(), staged)).is_err() {
+                    return;
+                }
+                thread::sleep(REFRESH);
+            });
+            peers_rx.attach(None, move |(peers, staged)| {
+                apply.set_sensitive(staged);
+
+                for child in devices.children() {
+                    devices.remove(&child);
+                }
+                let group: Vec<_> = peers.iter().filter(|peer| peer.in_group).collect();
+                for peer in &group {
+                    let to = peer.peer_id.to_string();
+                    let label = match &peer.device {
+                        Some(device) => device.name.clone(),
+                        None => to.clone(),
+                    };
+                    let item = gtk::MenuItem::with_label(&label);
+                    let control_tx = control_tx.clone();
+                    item.connect_activate(move |_| {
+                        control_tx.send(Request::new(Control::SendTo(to.clone()))).unwrap();
+                    });
+                    devices.append(&item);
+                }
+                devices.show_all();
+                send.set_sensitive(!group.is_empty());
+
+                for child in known.children() {
+                    known.remove(&child);
+                }
+                let mut named = 0;
+                for peer in &peers {
+                    let device = match &peer.device {
+                        Some(device) => device,
+                        None => continue,
+                    };
+                    let seen = match (peer.online, peer.last_seen) {
+                        (true, _) => "online".to_string(),
+                        (false, Some(last_seen)) => format!("last seen {}", ago(last_seen)),
+                        (false, None) => "offline".to_string(),
+                    };
+                    let item = gtk::MenuItem::with_label(&format!("{device}: {seen}"));
+                    item.set_sensitive(false);
+                    known.append(&item);
+                    named += 1;
+                }
+                known.show_all();
+                presence.set_sensitive(named > 0);
+                glib::Continue(true)
+            });
 
             gtk::main();
         }
     } else if #[cfg(target_os = "macos")] {
-        pub fn start_tray() {
+        use tray_item::TrayItem;
+        // tray-item has no submenus, sending to a device is left to `uniclip send`
+        pub fn start_tray(control_tx: Sender<Request>, _state: crate::State) {
             let mut tray = TrayItem::new("Unified Clipboard", "").unwrap();
             tray.add_label("Unified Clipboard").unwrap();
+            {
+                let control_tx = control_tx.clone();
+                tray.add_menu_item("Share current clipboard", move || {
+                    control_tx.send(Request::new(Control::Share)).unwrap();
+                }).unwrap();
+            }
+            {
+                let control_tx = control_tx.clone();
+                tray.add_menu_item("Apply staged item", move || {
+                    control_tx.send(Request::new(Control::ApplyStaged)).unwrap();
+                }).unwrap();
+            }
+            tray.add_menu_item("Clear everywhere", move || {
+                control_tx.send(Request::new(Control::ClearEverywhere)).unwrap();
+            }).unwrap();
             let mut inner = tray.inner_mut();
             inner.add_quit_item("Quit");
             inner.display();
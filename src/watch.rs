@@ -0,0 +1,39 @@
+use crate::ipc::{Control, Request};
+use anyhow::bail;
+use notify::{DebouncedEvent, RecursiveMode, Watcher};
+use std::{
+    path::Path,
+    sync::mpsc::{self, Sender},
+    time::Duration,
+};
+use tracing::debug;
+
+pub fn watch(path: &Path, control_tx: Sender<Request>) -> anyhow::Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if dir.as_os_str().is_empty() => Path::new("."),
+        Some(dir) => dir,
+        None => bail!("invalid settings path: {}", path.display()),
+    };
+    let name = path.file_name();
+
+    // watch the directory, editors often replace the file instead of writing it
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = notify::watcher(tx, Duration::from_secs(1))?;
+    watcher.watch(dir, RecursiveMode::NonRecursive)?;
+
+    for event in rx {
+        let changed = match &event {
+            DebouncedEvent::Create(path)
+            | DebouncedEvent::Write(path)
+            | DebouncedEvent::Rename(_, path) => path.file_name() == name,
+            _ => false,
+        };
+        if changed {
+            debug!(?event, "settings file changed");
+            if control_tx.send(Request::new(Control::Reload)).is_err() {
+                break;
+            }
+        }
+    }
+    Ok(())
+}
@@ -3,15 +3,25 @@
 #[macro_use]
 extern crate lazy_static;
 
+use image::{DynamicImage, ImageOutputFormat, RgbaImage};
 use jni::{
     objects::{JClass, JObject, JString},
-    sys::jstring,
+    sys::{jboolean, jbyteArray, jstring, JNI_FALSE, JNI_TRUE},
     JNIEnv,
 };
-use log::{info, trace, Level};
-use std::sync::{Arc, Mutex};
+use log::{info, trace, warn, Level};
+use std::{
+    io::Cursor,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    thread::JoinHandle,
+};
+use uniclip_net::{Command, PeerInfo};
 use uniclip_proto::{clip_msg::MsgType, ClipMsg};
 
+mod settings;
+use settings::Settings;
+
 fn native_activity_create() {
     android_logger::init_once(
         android_logger::Config::default()
@@ -21,104 +31,319 @@ fn native_activity_create() {
 }
 
 lazy_static! {
-    static ref TOPIC: Mutex<String> = Mutex::new(String::from("uniclip"));
-    static ref TO_NET_TX: Mutex<Option<tokio::sync::mpsc::Sender<ClipMsg>>> = Mutex::new(None);
+    static ref NODE: Mutex<Option<Node>> = Mutex::new(None);
 }
 
-struct Net {
-    topic: String,
-    from_net_tx: std::sync::mpsc::Sender<ClipMsg>,
-    to_net_rx: tokio::sync::mpsc::Receiver<ClipMsg>,
+pub enum Incoming {
+    Text(String),
+    // png encoded
+    Image(Vec<u8>),
 }
 
-impl Net {
-    fn start(self) {
-        let config = uniclip_net::Config {
-            dir: ".".to_string(),
-            topic: self.topic,
-            relay_server_address: None,
-            relay_server_peer_id: None,
-        };
+pub type Callback = Arc<dyn Fn(Incoming) + Send + Sync>;
 
-        tokio::runtime::Builder::new_multi_thread()
-            .enable_all()
-            .build()
-            .unwrap()
-            .block_on(
-                async move { uniclip_net::trans(config, self.from_net_tx, self.to_net_rx).await },
-            );
-    }
+pub struct Node {
+    dir: PathBuf,
+    settings: Arc<Settings>,
+    callback: Callback,
+    to_net_tx: tokio::sync::mpsc::Sender<Command>,
+    threads: Vec<JoinHandle<()>>,
 }
 
-pub fn start_net<F>(topic: String, callback: F)
-where
-    F: Fn(String),
-{
-    let (from_net_tx, from_net_rx) = std::sync::mpsc::channel();
-    let (to_net_tx, to_net_rx) = tokio::sync::mpsc::channel(10);
+impl Node {
+    pub fn start(dir: PathBuf, settings: Settings, callback: Callback) -> Result<Self, String> {
+        let config = settings.net_config(dir.clone())?;
+        let settings = Arc::new(settings);
+
+        let (from_net_tx, from_net_rx) = std::sync::mpsc::channel();
+        let (to_net_tx, to_net_rx) = tokio::sync::mpsc::channel(10);
 
-    {
-        TO_NET_TX.lock().unwrap().replace(to_net_tx);
-        *TOPIC.lock().unwrap() = topic.clone();
+        let net = std::thread::spawn(move || {
+            tokio::runtime::Builder::new_multi_thread()
+                .enable_all()
+                .build()
+                .unwrap()
+                .block_on(uniclip_net::trans(config, from_net_tx, to_net_rx));
+            info!("net stopped");
+        });
+
+        // ends once the net thread drops from_net_tx
+        let receiver = {
+            let settings = settings.clone();
+            let callback = callback.clone();
+            std::thread::spawn(move || {
+                while let Ok(uniclip_net::Received { clip_msg: msg, .. }) = from_net_rx.recv() {
+                    match settings.limits.accepts(&msg) {
+                        Ok(_) => receive(&callback, msg),
+                        Err(reason) => warn!("drop clip from net: {reason}"),
+                    }
+                }
+            })
+        };
+
+        Ok(Self {
+            dir,
+            settings,
+            callback,
+            to_net_tx,
+            threads: vec![net, receiver],
+        })
     }
 
-    let net = Net {
-        topic,
-        from_net_tx,
-        to_net_rx,
-    };
+    pub fn stop(self) -> Callback {
+        drop(self.to_net_tx);
+        for thread in self.threads {
+            let _ = thread.join();
+        }
+        self.callback
+    }
 
-    let callback = Arc::new(callback);
+    pub fn publish(&self, msg: ClipMsg) {
+        if let Err(reason) = self.settings.limits.accepts(&msg) {
+            warn!("drop local clipboard item: {reason}");
+            return;
+        }
+        if let Err(err) = self.to_net_tx.blocking_send(Command::Publish(msg)) {
+            warn!("publish failed: {err}");
+        }
+    }
 
-    std::thread::spawn(move || {
-        net.start();
-    });
+    pub fn peers(&self) -> Vec<PeerInfo> {
+        let (reply_tx, reply_rx) = tokio::sync::oneshot::channel();
+        if self
+            .to_net_tx
+            .blocking_send(Command::Peers(reply_tx))
+            .is_err()
+        {
+            return Vec::new();
+        }
+        reply_rx.blocking_recv().unwrap_or_default()
+    }
+}
 
-    loop {
-        if let Ok(msg) = from_net_rx.recv() {
-            match msg.typ() {
-                uniclip_proto::clip_msg::MsgType::Text => {
-                    let text = msg.text();
-                    info!("receive from net: {text}");
-                    callback.clone()(text.to_string());
-                }
-                uniclip_proto::clip_msg::MsgType::Image => {
-                    let image = msg.image.unwrap();
-                    info!("receive from net: image");
-                }
+fn receive(callback: &Callback, msg: ClipMsg) {
+    match msg.typ() {
+        MsgType::Text => {
+            let text = msg.text();
+            // the content stays out of logcat, other apps with READ_LOGS could see it
+            info!(
+                "receive from net, sent by {:?}: text of {} bytes",
+                msg.device(),
+                text.len()
+            );
+            callback(Incoming::Text(text.to_string()));
+        }
+        MsgType::Image => {
+            let image = match msg.image.as_ref() {
+                Some(image) => image,
+                None => return,
+            };
+            info!(
+                "receive from net, sent by {:?}: image {}x{}",
+                msg.device(),
+                image.width,
+                image.height
+            );
+            match encode_png(image) {
+                Some(png) => callback(Incoming::Image(png)),
+                None => warn!("invalid image from net"),
             }
         }
+        MsgType::Clear => {
+            info!("receive from net: clear");
+        }
     }
 }
 
+fn encode_png(image: &uniclip_proto::clip_msg::ImageData) -> Option<Vec<u8>> {
+    let image = RgbaImage::from_raw(image.width, image.height, image.data.clone())?;
+    let mut png = Cursor::new(Vec::new());
+    DynamicImage::ImageRgba8(image)
+        .write_to(&mut png, ImageOutputFormat::Png)
+        .ok()?;
+    Some(png.into_inner())
+}
+
+fn publish(msg: ClipMsg) {
+    match NODE.lock().unwrap().as_ref() {
+        Some(node) => node.publish(msg),
+        None => warn!("node is not running, drop clipboard item"),
+    }
+}
+
+// calls back into the java object from whichever thread receives the item
+fn java_callback(env: &JNIEnv, callback: JObject) -> Callback {
+    let vm = env.get_java_vm().expect("Couldn't get java vm!");
+    let callback = env
+        .new_global_ref(callback)
+        .expect("Couldn't create global ref!");
+
+    Arc::new(move |incoming| {
+        let env = match vm.attach_current_thread_permanently() {
+            Ok(env) => env,
+            Err(err) => {
+                warn!("attach thread failed: {err}");
+                return;
+            }
+        };
+        let result = match incoming {
+            Incoming::Text(text) => env.new_string(text).and_then(|output| {
+                env.call_method(
+                    callback.as_obj(),
+                    "copyToClipboard",
+                    "(Ljava/lang/String;)V",
+                    &[output.into()],
+                )
+            }),
+            Incoming::Image(png) => env.byte_array_from_slice(&png).and_then(|output| {
+                env.call_method(
+                    callback.as_obj(),
+                    "copyImageToClipboard",
+                    "([B)V",
+                    &[JObject::from(output).into()],
+                )
+            }),
+        };
+        if let Err(err) = result {
+            warn!("callback failed: {err}");
+            let _ = env.exception_clear();
+        }
+    })
+}
+
+fn throw(env: &JNIEnv, err: String) {
+    warn!("{err}");
+    let _ = env.throw_new("java/lang/IllegalArgumentException", err);
+}
+
 #[no_mangle]
-pub extern "system" fn Java_com_zu1k_uniclip_MainActivity_start(
+pub extern "system" fn Java_com_zu1k_uniclip_Node_start(
     env: JNIEnv,
     _class: JClass,
-    topic: JString,
+    files_dir: JString,
+    config: JString,
     callback: JObject,
 ) {
     native_activity_create();
 
-    let topic: String = env
-        .get_string(topic)
+    let dir: String = env
+        .get_string(files_dir)
         .expect("Couldn't get java string!")
         .into();
+    let config: String = env
+        .get_string(config)
+        .expect("Couldn't get java string!")
+        .into();
+
+    let settings = match Settings::from_json(&config) {
+        Ok(settings) => settings,
+        Err(err) => return throw(&env, err),
+    };
+    trace!("dir: {dir}, topic: {}", settings.topic);
 
-    trace!("topic: {topic}");
+    // stop outside of the lock, the receiver may be publishing through it
+    let running = NODE.lock().unwrap().take();
+    if let Some(node) = running {
+        node.stop();
+    }
+    match Node::start(PathBuf::from(dir), settings, java_callback(&env, callback)) {
+        Ok(node) => {
+            NODE.lock().unwrap().replace(node);
+        }
+        Err(err) => throw(&env, err),
+    }
+}
 
-    let on_net_reveive = |text: String| {
-        let output = env.new_string(text).expect("Couldn't create java string!");
-        env.call_method(
-            callback,
-            "copyToClipboard",
-            "(Ljava/lang/String;)V",
-            &[output.into()],
-        )
-        .unwrap();
+#[no_mangle]
+pub extern "system" fn Java_com_zu1k_uniclip_Node_stop(env: JNIEnv, _class: JClass) {
+    let running = NODE.lock().unwrap().take();
+    if let Some(node) = running {
+        info!("stop node {}", node.settings.topic);
+        node.stop();
+    }
+}
+
+#[no_mangle]
+pub extern "system" fn Java_com_zu1k_uniclip_Node_restart(
+    env: JNIEnv,
+    _class: JClass,
+    config: JString,
+) -> jboolean {
+    let config: String = env
+        .get_string(config)
+        .expect("Couldn't get java string!")
+        .into();
+
+    let settings = match Settings::from_json(&config) {
+        Ok(settings) => settings,
+        Err(err) => {
+            throw(&env, err);
+            return JNI_FALSE;
+        }
     };
 
-    start_net(topic, on_net_reveive)
+    let running = NODE.lock().unwrap().take();
+    match running {
+        Some(running) => {
+            info!(
+                "restart node {} as {}",
+                running.settings.topic, settings.topic
+            );
+            let dir = running.dir.clone();
+            let callback = running.stop();
+            match Node::start(dir, settings, callback) {
+                Ok(node) => {
+                    NODE.lock().unwrap().replace(node);
+                    JNI_TRUE
+                }
+                Err(err) => {
+                    throw(&env, err);
+                    JNI_FALSE
+                }
+            }
+        }
+        None => {
+            warn!("node is not running, start it first");
+            JNI_FALSE
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "system" fn Java_com_zu1k_uniclip_Node_status(env: JNIEnv, _class: JClass) -> jstring {
+    let status = match NODE.lock().unwrap().as_ref() {
+        Some(node) => {
+            let all = node.peers();
+            let peers: Vec<_> = all.iter().filter(|peer| peer.in_group).collect();
+            let outdated = peers
+                .iter()
+                .filter(|peer| matches!(&peer.version, Some(version) if !version.compatible()))
+                .count();
+            let mut status = format!("running {}, {} peers", node.settings.topic, peers.len());
+            if outdated > 0 {
+                status.push_str(&format!(", {outdated} need an update"));
+            }
+            // one line per named device, including the ones that went away
+            for peer in &all {
+                if let Some(device) = &peer.device {
+                    let seen = match (peer.online, peer.last_seen) {
+                        (true, _) => "online".to_string(),
+                        (false, Some(last_seen)) => {
+                            let mins = uniclip_net::now_millis().saturating_sub(last_seen) / 60_000;
+                            format!("seen {mins} min ago")
+                        }
+                        (false, None) => "offline".to_string(),
+                    };
+                    status.push_str(&format!("\n{device}: {seen}"));
+                }
+            }
+            status
+        }
+        None => "stopped".to_string(),
+    };
+    env.new_string(status)
+        .expect("Couldn't create java string!")
+        .into_inner()
 }
 
 #[no_mangle]
@@ -136,13 +361,35 @@ pub extern "system" fn Java_com_zu1k_uniclip_ClipboardMonitorService_clipPublish
     msg.set_typ(MsgType::Text);
     msg.text = Some(text);
 
-    TO_NET_TX
-        .lock()
-        .unwrap()
-        .as_ref()
-        .unwrap()
-        .blocking_send(msg)
-        .unwrap();
+    publish(msg);
+}
+
+#[no_mangle]
+pub extern "system" fn Java_com_zu1k_uniclip_ClipboardMonitorService_clipPublishImage(
+    env: JNIEnv,
+    _class: JClass,
+    data: jbyteArray,
+) {
+    let data = env
+        .convert_byte_array(data)
+        .expect("Couldn't get java byte array!");
+    let image = match image::load_from_memory(&data) {
+        Ok(image) => image.to_rgba8(),
+        Err(err) => {
+            warn!("unsupported image: {err}");
+            return;
+        }
+    };
+
+    let mut msg = ClipMsg::default();
+    msg.set_typ(MsgType::Image);
+    msg.image = Some(uniclip_proto::clip_msg::ImageData {
+        width: image.width(),
+        height: image.height(),
+        data: image.into_raw(),
+    });
+
+    publish(msg);
 }
 
 #[no_mangle]
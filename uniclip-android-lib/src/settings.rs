@@ -0,0 +1,78 @@
+use serde::Deserialize;
+use std::path::PathBuf;
+use uniclip_net::{limits::Limits, Multiaddr};
+
+// passed from java as json, missing fields fall back to the defaults
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings {
+    pub topic: String,
+    pub secret: Option<String>,
+    // shown on the other devices, the app passes Build.MODEL
+    pub device_name: String,
+
+    pub relays: Vec<String>,
+    pub listen_addrs: Vec<String>,
+
+    pub limits: Limits,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        let net = uniclip_net::Config::default();
+        Self {
+            topic: net.groups[0].topic.clone(),
+            secret: None,
+            device_name: "Android".to_string(),
+
+            relays: net.relays.iter().map(ToString::to_string).collect(),
+            listen_addrs: net.listen_addrs.iter().map(ToString::to_string).collect(),
+
+            limits: Limits::default(),
+        }
+    }
+}
+
+impl Settings {
+    pub fn from_json(json: &str) -> Result<Self, String> {
+        let settings: Settings =
+            serde_json::from_str(json).map_err(|err| format!("invalid config: {err}"))?;
+        if settings.topic.trim().is_empty() {
+            return Err("topic: must not be empty".to_string());
+        }
+        if matches!(&settings.secret, Some(secret) if secret.len() < 8) {
+            return Err("secret: must be at least 8 characters".to_string());
+        }
+        if settings.device_name.trim().is_empty() {
+            return Err("device_name: must not be empty".to_string());
+        }
+        Ok(settings)
+    }
+
+    pub fn net_config(&self, dir: PathBuf) -> Result<uniclip_net::Config, String> {
+        let parse = |addresses: &[String]| {
+            addresses
+                .iter()
+                .map(|address| {
+                    address
+                        .parse::<Multiaddr>()
+                        .map_err(|err| format!("{address:?}: {err}"))
+                })
+                .collect::<Result<Vec<_>, _>>()
+        };
+
+        Ok(uniclip_net::Config {
+            dir,
+            groups: vec![uniclip_net::GroupConfig {
+                topic: self.topic.clone(),
+                secret: self.secret.clone(),
+            }],
+
+            listen_addrs: parse(&self.listen_addrs)?,
+            relays: parse(&self.relays)?,
+            mailbox: None,
+            device_name: self.device_name.trim().to_string(),
+            max_message_size: uniclip_net::max_message_size(self.limits.largest()),
+        })
+    }
+}
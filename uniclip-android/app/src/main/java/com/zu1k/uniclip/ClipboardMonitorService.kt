@@ -6,13 +6,18 @@ import android.content.Intent
 import android.os.IBinder
 import android.content.ClipboardManager
 import android.content.Context
+import android.net.Uri
+import androidx.core.content.FileProvider
+import java.io.File
 import java.lang.Exception
 
 class ClipboardMonitorService(): Service() {
     private lateinit var clipboard: ClipboardManager
+    private lateinit var ctx: Context
     private var lastText = "";
 
     constructor(context: Context) : this() {
+        ctx = context
         clipboard = context!!.getSystemService(Context.CLIPBOARD_SERVICE) as ClipboardManager
     }
 
@@ -26,6 +31,36 @@ class ClipboardMonitorService(): Service() {
         }
     }
 
+    fun copyImageToClipboard(png: ByteArray) {
+        println("copyImageToClipboard: ${png.size} bytes")
+        return try {
+            val dir = File(ctx.cacheDir, "images")
+            dir.mkdirs()
+            val file = File(dir, "clip-${System.currentTimeMillis()}.png")
+            dir.listFiles()?.forEach { it.delete() }
+            file.writeBytes(png)
+
+            val uri = FileProvider.getUriForFile(ctx, "${ctx.packageName}.fileprovider", file)
+            val clip = ClipData.newUri(ctx.contentResolver, "UniClip", uri)
+            clipboard.setPrimaryClip(clip)
+            lastText = uri.toString()
+        } catch (e: Exception) {
+        }
+    }
+
+    private fun readImageFromClipboard(): ByteArray? {
+        val uri = clipboard.primaryClip?.getItemAt(0)?.uri ?: return null
+        val type = ctx.contentResolver.getType(uri) ?: return null
+        if (!type.startsWith("image/")) {
+            return null
+        }
+        return try {
+            ctx.contentResolver.openInputStream(uri)?.use { it.readBytes() }
+        } catch (e: Exception) {
+            null
+        }
+    }
+
     fun readFromClipboard(): String {
         val clip = clipboard.primaryClip
         if (clip != null) {
@@ -48,6 +83,7 @@ class ClipboardMonitorService(): Service() {
 
     override fun onCreate() {
         super.onCreate()
+        ctx = this
         clipboard = getSystemService(Context.CLIPBOARD_SERVICE) as ClipboardManager
         clipboard.addPrimaryClipChangedListener(mOnPrimaryClipChangedListener)
     }
@@ -66,7 +102,12 @@ class ClipboardMonitorService(): Service() {
         println("clipboard changed: $text")
         if (text != lastText) {
             lastText = text
-            clipPublishText(text)
+            val image = readImageFromClipboard()
+            if (image != null) {
+                clipPublishImage(image)
+            } else {
+                clipPublishText(text)
+            }
             println("down")
         }
     }
@@ -77,6 +118,7 @@ class ClipboardMonitorService(): Service() {
         }
 
     external fun clipPublishText(text: String)
+    external fun clipPublishImage(data: ByteArray)
 
     companion object {
         init {
@@ -1,9 +1,12 @@
 package com.zu1k.uniclip
 
 import androidx.appcompat.app.AppCompatActivity
+import android.os.Build
 import android.os.Bundle
 import android.content.Intent
 import com.zu1k.uniclip.databinding.ActivityMainBinding
+import org.json.JSONObject
+import java.io.File
 import kotlin.concurrent.thread
 
 class MainActivity : AppCompatActivity() {
@@ -24,20 +27,53 @@ class MainActivity : AppCompatActivity() {
         startService(Intent(this, ClipboardMonitorService::class.java))
 
         binding.button.setOnClickListener {
-            val topic = binding.editTextTextTopic.text.toString()
-            thread(start = true) {
-                println("running from thread(): ${Thread.currentThread()}")
-                start(topic, clipboard)
+            if (Node.status() == "stopped") {
+                val config = loadConfig(binding.editTextTextTopic.text.toString())
+                thread(start = true) {
+                    println("running from thread(): ${Thread.currentThread()}")
+                    try {
+                        Node.start(filesDir.absolutePath, config, clipboard)
+                    } catch (e: IllegalArgumentException) {
+                        println("start failed: ${e.message}")
+                    }
+                    runOnUiThread { updateButton() }
+                }
+            } else {
+                thread(start = true) {
+                    Node.stop()
+                    runOnUiThread { updateButton() }
+                }
             }
+        }
+        updateButton()
+    }
+
+    // config.json in the files dir may also set secret, device_name, relays, listen_addrs and limits
+    private fun loadConfig(topic: String): String {
+        val file = File(filesDir, "config.json")
+        val config = if (file.exists()) JSONObject(file.readText()) else JSONObject()
+        config.put("topic", topic)
+        if (!config.has("device_name")) {
+            config.put("device_name", Build.MODEL)
+        }
+        file.writeText(config.toString(2))
+        return config.toString()
+    }
+
+    private fun updateButton() {
+        val status = Node.status()
+        // peer count, warnings about peers that need an update and the known devices
+        binding.sampleText.text = status
+        if (status == "stopped") {
+            binding.button.setBackgroundColor(resources.getColor(R.color.purple_500, theme))
+            binding.button.text = "Start"
+        } else {
             binding.button.setBackgroundColor(resources.getColor(R.color.red, theme))
             binding.button.text = "Stop"
-
-            binding.button.setOnClickListener {  }
         }
     }
 
     external fun stringFromJNI(): String
-    external fun start(topic: String, callback: ClipboardMonitorService)
 
     companion object {
         init {
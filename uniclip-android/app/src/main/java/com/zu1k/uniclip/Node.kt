@@ -0,0 +1,15 @@
+package com.zu1k.uniclip
+
+object Node {
+    // returns immediately, received items are delivered to callback from a background thread.
+    // filesDir keeps the node identity, config is json with topic, secret, relays,
+    // listen_addrs and limits; an invalid config throws IllegalArgumentException
+    external fun start(filesDir: String, config: String, callback: ClipboardMonitorService)
+    external fun stop()
+    external fun restart(config: String): Boolean
+    external fun status(): String
+
+    init {
+        System.loadLibrary("uniclip")
+    }
+}
@@ -0,0 +1,97 @@
+use chacha20poly1305::{
+    aead::{Aead, NewAead},
+    ChaCha20Poly1305, Key, Nonce,
+};
+use hkdf::Hkdf;
+use sha2::Sha256;
+
+const NONCE_LEN: usize = 12;
+const KEY_SALT: &[u8] = b"uniclip group key v1";
+const KEY_INFO: &[u8] = b"uniclip chacha20poly1305";
+
+pub struct Cipher {
+    aead: ChaCha20Poly1305,
+}
+
+impl Cipher {
+    pub fn new(topic: &str, secret: &str) -> Self {
+        // length prefixes keep ("ab", "c") and ("a", "bc") apart
+        let mut ikm = Vec::new();
+        for part in [topic, secret] {
+            ikm.extend_from_slice(&(part.len() as u64).to_be_bytes());
+            ikm.extend_from_slice(part.as_bytes());
+        }
+        let mut key = [0; 32];
+        Hkdf::<Sha256>::new(Some(KEY_SALT), &ikm)
+            .expand(KEY_INFO, &mut key)
+            .expect("valid key length");
+
+        Self {
+            aead: ChaCha20Poly1305::new(Key::from_slice(&key)),
+        }
+    }
+
+    pub fn encrypt(&self, plain: &[u8]) -> Vec<u8> {
+        let nonce: [u8; NONCE_LEN] = rand::random();
+        let sealed = self
+            .aead
+            .encrypt(Nonce::from_slice(&nonce), plain)
+            .expect("encryption failure");
+
+        let mut data = Vec::with_capacity(NONCE_LEN + sealed.len());
+        data.extend_from_slice(&nonce);
+        data.extend_from_slice(&sealed);
+        data
+    }
+
+    pub fn decrypt(&self, data: &[u8]) -> Option<Vec<u8>> {
+        if data.len() < NONCE_LEN {
+            return None;
+        }
+        let (nonce, sealed) = data.split_at(NONCE_LEN);
+        self.aead.decrypt(Nonce::from_slice(nonce), sealed).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    proptest! {
+        #[test]
+        fn roundtrips(plain in prop::collection::vec(any::<u8>(), 0..256)) {
+            let cipher = Cipher::new("topic", "secret");
+            prop_assert_eq!(cipher.decrypt(&cipher.encrypt(&plain)), Some(plain));
+        }
+
+        #[test]
+        fn rejects_tampering(plain in prop::collection::vec(any::<u8>(), 0..256), flip in any::<usize>()) {
+            let cipher = Cipher::new("topic", "secret");
+            let mut data = cipher.encrypt(&plain);
+            let index = flip % data.len();
+            data[index] ^= 1;
+            prop_assert_eq!(cipher.decrypt(&data), None);
+        }
+
+        #[test]
+        fn rejects_other_keys(plain in prop::collection::vec(any::<u8>(), 0..256)) {
+            let data = Cipher::new("topic", "secret").encrypt(&plain);
+            prop_assert_eq!(Cipher::new("topic", "other").decrypt(&data), None);
+            prop_assert_eq!(Cipher::new("other", "secret").decrypt(&data), None);
+        }
+
+        #[test]
+        fn topic_and_secret_do_not_run_together(split in 1usize..5) {
+            let joined = "topicsecret";
+            let data = Cipher::new("topic", "secret").encrypt(b"item");
+            let (topic, secret) = joined.split_at(split);
+            prop_assert_eq!(Cipher::new(topic, secret).decrypt(&data), None);
+        }
+
+        #[test]
+        fn arbitrary_bytes_never_panic(data in prop::collection::vec(any::<u8>(), 0..64)) {
+            prop_assert_eq!(Cipher::new("topic", "secret").decrypt(&data), None);
+        }
+    }
+}
@@ -0,0 +1,96 @@
+use async_trait::async_trait;
+use futures::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use libp2p::{
+    core::{
+        upgrade::{read_length_prefixed, write_length_prefixed},
+        ProtocolName,
+    },
+    request_response::RequestResponseCodec,
+};
+use std::io;
+
+// a clip for one peer instead of the whole group, the payload is the same as on gossipsub
+pub const PROTOCOL_NAME: &str = "/uniclip/direct/1.0.0";
+
+#[derive(Debug, Clone)]
+pub struct DirectProtocol;
+
+impl ProtocolName for DirectProtocol {
+    fn protocol_name(&self) -> &[u8] {
+        PROTOCOL_NAME.as_bytes()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DirectResponse {
+    Accepted,
+    // not decryptable or not valid for the receiver
+    Rejected,
+}
+
+// reads requests of up to the configured message size
+#[derive(Debug, Clone)]
+pub struct DirectCodec {
+    max_message_size: usize,
+}
+
+impl DirectCodec {
+    pub fn new(max_message_size: usize) -> Self {
+        Self { max_message_size }
+    }
+}
+
+#[async_trait]
+impl RequestResponseCodec for DirectCodec {
+    type Protocol = DirectProtocol;
+    type Request = Vec<u8>;
+    type Response = DirectResponse;
+
+    async fn read_request<T>(&mut self, _: &DirectProtocol, io: &mut T) -> io::Result<Self::Request>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        read_length_prefixed(io, self.max_message_size).await
+    }
+
+    async fn read_response<T>(
+        &mut self,
+        _: &DirectProtocol,
+        io: &mut T,
+    ) -> io::Result<Self::Response>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        match read_length_prefixed(io, 1).await?.as_slice() {
+            [1] => Ok(DirectResponse::Accepted),
+            _ => Ok(DirectResponse::Rejected),
+        }
+    }
+
+    async fn write_request<T>(
+        &mut self,
+        _: &DirectProtocol,
+        io: &mut T,
+        request: Self::Request,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        write_length_prefixed(io, request).await?;
+        io.close().await
+    }
+
+    async fn write_response<T>(
+        &mut self,
+        _: &DirectProtocol,
+        io: &mut T,
+        response: Self::Response,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        let accepted = response == DirectResponse::Accepted;
+        write_length_prefixed(io, [accepted as u8]).await?;
+        io.close().await
+    }
+}
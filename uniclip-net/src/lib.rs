@@ -1,99 +1,290 @@
-use futures::{executor::block_on, StreamExt};
+use futures::StreamExt;
 use libp2p::{
     autonat,
-    core::{transport::OrTransport, upgrade},
+    core::{
+        muxing::StreamMuxerBox,
+        transport::{Boxed, ListenerId},
+    },
     dcutr::{
         self,
         behaviour::{Behaviour as DcutrBehaviour, Event as DcutrEvent},
     },
-    dns::DnsConfig,
     gossipsub::{
         self, error::PublishError, Gossipsub, GossipsubEvent, IdentTopic as Topic,
-        MessageAuthenticity, ValidationMode,
+        MessageAuthenticity, TopicHash, ValidationMode,
     },
     identify::{Identify, IdentifyConfig, IdentifyEvent},
-    identity::{self, Keypair},
-    mdns::{Mdns, MdnsEvent},
-    mplex::MplexConfig,
+    identity::Keypair,
     multiaddr::Protocol,
-    noise,
     relay::v2::client::{self, Client as RelayClient, Event as RelayEvent},
-    swarm::{NetworkBehaviourEventProcess, SwarmBuilder, SwarmEvent},
-    tcp::TcpConfig,
-    Multiaddr, NetworkBehaviour, PeerId, Transport,
+    request_response::{
+        ProtocolSupport, RequestId, RequestResponse, RequestResponseConfig, RequestResponseEvent,
+        RequestResponseMessage,
+    },
+    swarm::{toggle::Toggle, NetworkBehaviourEventProcess, Swarm, SwarmBuilder, SwarmEvent},
+    NetworkBehaviour, Transport,
 };
 use prost::Message;
 use std::{
-    fs,
-    io::{Read, Write},
-    path,
+    collections::{BTreeSet, HashMap, HashSet},
+    fmt, iter,
+    path::PathBuf,
     sync::mpsc::Sender,
     time::Duration,
 };
 use tokio::sync::mpsc::Receiver;
+use tracing::{debug, debug_span, error, info, warn};
 use uniclip_proto::ClipMsg;
 
-pub fn get_local_keypair_peerid(config: &Config) -> (Keypair, PeerId) {
-    let filepath = path::Path::new(&config.dir).join("keypair");
-
-    let keypair = match fs::File::open(&filepath) {
-        Ok(mut file) => {
-            let mut buffer = vec![0; file.metadata().unwrap().len() as usize];
-            file.read(&mut buffer).expect("buffer overflow");
-            let keypair = Keypair::from_protobuf_encoding(&buffer).unwrap();
-            keypair
-        }
-        Err(_) => {
-            let keypair = identity::Keypair::generate_ed25519();
-            let buffer = keypair.to_protobuf_encoding().unwrap();
-            if let Ok(mut file) = fs::File::create(&filepath) {
-                file.write(&buffer).unwrap();
-            }
-            keypair
-        }
-    };
+#[cfg(not(target_arch = "wasm32"))]
+use libp2p::mdns::MdnsEvent;
+
+mod crypto;
+pub use crypto::Cipher;
+mod direct;
+use direct::{DirectCodec, DirectProtocol, DirectResponse};
+#[cfg(not(target_arch = "wasm32"))]
+mod native;
+#[cfg(not(target_arch = "wasm32"))]
+use native as platform;
+#[cfg(target_arch = "wasm32")]
+mod wasm;
+#[cfg(target_arch = "wasm32")]
+use wasm as platform;
+pub mod limits;
+pub mod mailbox;
+pub mod presence;
+use presence::{Device, Presence};
+pub mod version;
+use version::{Capability, PeerVersion, PROTOCOL_VERSION};
+#[cfg(all(feature = "test-util", not(target_arch = "wasm32")))]
+pub mod testing;
+pub use mailbox::now_millis;
+use mailbox::{Mailbox, MailboxCodec, MailboxConfig, MailboxItem, MailboxProtocol};
+
+pub use libp2p::{Multiaddr, PeerId};
+
+// bound of every message, the gossipsub transmit size can be lower
+pub const MAX_MESSAGE_SIZE: usize = 1024 * 1024 * 50;
+
+pub const DEV_RELAY: &str =
+    "/ip4/42.193.117.213/tcp/34567/p2p/12D3KooWNoSoxPRWovwRFnheDwrgo6cufbYGtWSrfKXVhSDxTzSV";
 
+pub fn get_local_keypair_peerid(config: &Config) -> (Keypair, PeerId) {
+    let keypair = platform::load_keypair(config);
     let peer_id = PeerId::from(keypair.public());
     (keypair, peer_id)
 }
 
 pub struct Config {
-    pub dir: String,
+    pub dir: PathBuf,
+    // one gossipsub topic each, items are published to all of them unless `PublishTo` picks
+    pub groups: Vec<GroupConfig>,
+
+    pub listen_addrs: Vec<Multiaddr>,
+    // full addresses including the relay's /p2p/ peer id
+    pub relays: Vec<Multiaddr>,
+    // serve as mailbox for offline peers
+    pub mailbox: Option<MailboxConfig>,
+    // shown to the other peers, changes need a restart
+    pub device_name: String,
+    // bytes of a sealed item, see `max_message_size`
+    pub max_message_size: usize,
+}
+
+impl fmt::Debug for Config {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Config")
+            .field("dir", &self.dir)
+            .field("groups", &self.groups)
+            .field("listen_addrs", &self.listen_addrs)
+            .field("relays", &self.relays)
+            .field("mailbox", &self.mailbox)
+            .field("device_name", &self.device_name)
+            .field("max_message_size", &self.max_message_size)
+            .finish()
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            dir: PathBuf::from("."),
+            groups: vec![GroupConfig {
+                topic: "uniclip".to_string(),
+                secret: None,
+            }],
+
+            listen_addrs: vec!["/ip4/0.0.0.0/tcp/0".parse().unwrap()],
+            relays: vec![DEV_RELAY.parse().unwrap()],
+            mailbox: None,
+            device_name: "uniclip".to_string(),
+            max_message_size: MAX_MESSAGE_SIZE,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Eq)]
+pub struct GroupConfig {
     pub topic: String,
+    pub secret: Option<String>,
+}
 
-    pub relay_server_address: Option<Multiaddr>,
-    pub relay_server_peer_id: Option<PeerId>,
+impl fmt::Debug for GroupConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GroupConfig")
+            .field("topic", &self.topic)
+            .field("secret", &self.secret.as_ref().map(|_| "<redacted>"))
+            .finish()
+    }
 }
 
-pub async fn trans(
-    config: Config,
-    from_net_tx: Sender<ClipMsg>,
-    to_net_rx: Receiver<ClipMsg>,
-) -> ! {
-    let (local_key, local_peer_id) = get_local_keypair_peerid(&config);
-    println!("Local peer id: {:?}", local_peer_id);
+/// An item from the network and the topic of the group it came through.
+#[derive(Debug)]
+pub struct Received {
+    pub topic: String,
+    pub clip_msg: ClipMsg,
+    pub origin: Origin,
+}
 
-    let topic = Topic::new(config.topic);
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Origin {
+    // gossipsub or a direct send, as it was copied
+    Live,
+    // fetched after being offline, `newest` is the last item of the fetch
+    Mailbox { newest: bool },
+}
+
+#[derive(Debug)]
+pub enum Command {
+    // to every group
+    Publish(ClipMsg),
+    PublishTo {
+        topics: Vec<String>,
+        clip_msg: ClipMsg,
+    },
+    // applies groups and relays, the other fields need a restart
+    Reconfigure(Config),
+    // fetch missed items from the mailboxes now
+    Sync,
+    Peers(tokio::sync::oneshot::Sender<Vec<PeerInfo>>),
+    // to a single peer, through a group both are in, replies once the peer took it
+    Send {
+        to: PeerId,
+        clip_msg: ClipMsg,
+        reply_tx: tokio::sync::oneshot::Sender<Result<(), String>>,
+    },
+}
 
-    let noise_keys = noise::Keypair::<noise::X25519Spec>::new()
-        .into_authentic(&local_key)
-        .expect("Signing libp2p-noise static DH keypair failed.");
+#[derive(Debug, Clone)]
+pub struct PeerInfo {
+    pub peer_id: PeerId,
+    // subscribed to one of our topics
+    pub in_group: bool,
+    // the topics we share
+    pub groups: Vec<String>,
+    pub mailbox: bool,
+    // None until identify ran, or for peers that aren't uniclip nodes
+    pub version: Option<PeerVersion>,
+    pub device: Option<Device>,
+    // connected now, otherwise a device seen earlier
+    pub online: bool,
+    // milliseconds since the unix epoch
+    pub last_seen: Option<u64>,
+}
+
+/// The largest message for items of up to `largest` bytes, with room for the other
+/// fields, encryption and gossipsub framing.
+pub fn max_message_size(largest: usize) -> usize {
+    largest.saturating_add(64 * 1024)
+}
+
+pub fn relay_peer_id(address: &Multiaddr) -> Option<PeerId> {
+    match address.iter().last() {
+        Some(Protocol::P2p(hash)) => PeerId::from_multihash(hash).ok(),
+        _ => None,
+    }
+}
+
+fn connect_relay(swarm: &mut Swarm<Behaviour>, relay_address: &Multiaddr) -> Option<ListenerId> {
+    let relay_peer_id = match relay_peer_id(relay_address) {
+        Some(peer_id) => peer_id,
+        None => {
+            warn!(%relay_address, "relay address without /p2p/ peer id");
+            return None;
+        }
+    };
+
+    let mut server_address = relay_address.clone();
+    server_address.pop();
+    swarm
+        .behaviour_mut()
+        .auto_nat
+        .add_server(relay_peer_id, Some(server_address));
+
+    match swarm.listen_on(relay_address.clone().with(Protocol::P2pCircuit)) {
+        Ok(listener_id) => Some(listener_id),
+        Err(err) => {
+            error!(%relay_address, "listen on relay failed: {err}");
+            None
+        }
+    }
+}
+
+fn disconnect_relay(
+    swarm: &mut Swarm<Behaviour>,
+    relay_address: &Multiaddr,
+    listener_id: ListenerId,
+) {
+    if let Some(relay_peer_id) = relay_peer_id(relay_address) {
+        swarm.behaviour_mut().auto_nat.remove_server(&relay_peer_id);
+    }
+    swarm.remove_listener(listener_id);
+}
 
+pub async fn trans(config: Config, from_net_tx: Sender<Received>, to_net_rx: Receiver<Command>) {
+    let (local_key, local_peer_id) = get_local_keypair_peerid(&config);
     let (relay_transport, relay_client) = RelayClient::new_transport_and_behaviour(local_peer_id);
+    let node = Node {
+        transport: platform::transport(&local_key, relay_transport),
+        local_key,
+        relay_client,
+        discovery: Some(platform::discovery().await),
+        dial: Vec::new(),
+    };
+    run(node, config, from_net_tx, to_net_rx).await
+}
 
-    let transport = OrTransport::new(
-        block_on(DnsConfig::system(TcpConfig::new().port_reuse(true))).unwrap(),
-        relay_transport,
-    )
-    .upgrade(upgrade::Version::V1)
-    .authenticate(noise::NoiseConfig::xx(noise_keys).into_authenticated())
-    .multiplex(MplexConfig::new())
-    .boxed();
+// what differs between a real node and one in the test harness
+struct Node {
+    local_key: Keypair,
+    transport: Boxed<(PeerId, StreamMuxerBox)>,
+    relay_client: RelayClient,
+    discovery: Option<platform::Discovery>,
+    // peers to connect to on start, in addition to the relays
+    dial: Vec<Multiaddr>,
+}
+
+async fn run(
+    node: Node,
+    config: Config,
+    from_net_tx: Sender<Received>,
+    to_net_rx: Receiver<Command>,
+) {
+    let Node {
+        local_key,
+        transport,
+        relay_client,
+        discovery,
+        dial,
+    } = node;
+    let local_peer_id = PeerId::from(local_key.public());
+    info!(%local_peer_id, "local peer id");
 
     let gossipsub_config = gossipsub::GossipsubConfigBuilder::default()
         .heartbeat_interval(Duration::from_secs(10))
         .validation_mode(ValidationMode::Strict)
-        .max_transmit_size(1024 * 1024 * 50)
+        .max_transmit_size(config.max_message_size)
         .build()
         .expect("Valid config");
 
@@ -103,14 +294,27 @@ pub async fn trans(
     )
     .expect("Correct configuration");
 
+    let mut mailbox_store = config.mailbox.clone().map(Mailbox::new);
+    let mailbox_support = match mailbox_store {
+        Some(_) => ProtocolSupport::Full,
+        None => ProtocolSupport::Outbound,
+    };
+    // peers that keep items for us, learned through identify
+    let mut mailboxes = HashSet::new();
+    let mut versions: HashMap<PeerId, PeerVersion> = HashMap::new();
+    let mut pending_sends = HashMap::new();
+
     let mut swarm = {
         let mut behaviour = Behaviour {
             gossipsub,
-            mdns: Mdns::new(Default::default()).await.unwrap(),
-            identify: Identify::new(IdentifyConfig::new(
-                "/uniclip/0.1.0".into(),
-                local_key.public(),
-            )),
+            mdns: discovery.into(),
+            identify: Identify::new(
+                IdentifyConfig::new(
+                    PeerVersion::local().to_protocol_version(),
+                    local_key.public(),
+                )
+                .with_agent_version(Device::local(&config.device_name).to_agent_version()),
+            ),
             auto_nat: autonat::Behaviour::new(
                 local_peer_id,
                 autonat::Config {
@@ -124,85 +328,383 @@ pub async fn trans(
 
             relay_client,
             dcutr: DcutrBehaviour::new(),
+            mailbox: RequestResponse::new(
+                MailboxCodec::new(config.max_message_size),
+                iter::once((MailboxProtocol, mailbox_support)),
+                RequestResponseConfig::default(),
+            ),
+            direct: RequestResponse::new(
+                DirectCodec::new(config.max_message_size),
+                iter::once((DirectProtocol, ProtocolSupport::Full)),
+                RequestResponseConfig::default(),
+            ),
 
             from_net_tx,
+            groups: Vec::new(),
+            fetches: HashMap::new(),
+            presence: HashMap::new(),
         };
 
-        behaviour.gossipsub.subscribe(&topic).unwrap();
+        for group in &config.groups {
+            behaviour.join(group);
+        }
 
         SwarmBuilder::new(transport, behaviour, local_peer_id)
-            // We want the connection background tasks to be spawned
-            // onto the tokio runtime.
-            .executor(Box::new(|fut| {
-                tokio::spawn(fut);
-            }))
+            .executor(Box::new(platform::spawn))
             .build()
     };
 
-    swarm
-        .listen_on("/ip4/0.0.0.0/tcp/0".parse().unwrap())
-        .unwrap();
+    for address in config.listen_addrs {
+        if let Err(err) = swarm.listen_on(address.clone()) {
+            error!(%address, "listen failed: {err}");
+        }
+    }
 
-    // connect relay
-    {
-        if let Some(relay_server_peer_id) = config.relay_server_peer_id {
-            swarm
-                .behaviour_mut()
-                .auto_nat
-                .add_server(relay_server_peer_id, config.relay_server_address.clone());
-
-            if let Some(relay_server_address) = config.relay_server_address {
-                swarm
-                    .listen_on(relay_server_address.with(Protocol::P2pCircuit))
-                    .unwrap();
-            }
+    for address in dial {
+        if let Err(err) = swarm.dial(address.clone()) {
+            error!(%address, "dial failed: {err}");
         }
+    }
 
-        // my dev server
-        let dev_relay_address: Multiaddr = "/ip4/42.193.117.213/tcp/34567".parse().unwrap();
-        let dev_relay_address_p2p: Multiaddr = "/ip4/42.193.117.213/tcp/34567/p2p/12D3KooWNoSoxPRWovwRFnheDwrgo6cufbYGtWSrfKXVhSDxTzSV".parse().unwrap();
-        swarm.behaviour_mut().auto_nat.add_server(
-            "12D3KooWNoSoxPRWovwRFnheDwrgo6cufbYGtWSrfKXVhSDxTzSV"
-                .parse()
-                .unwrap(),
-            Some(dev_relay_address.clone()),
-        );
-        swarm
-            .listen_on(dev_relay_address_p2p.with(Protocol::P2pCircuit))
-            .unwrap();
+    // connect relay
+    let mut relays = HashMap::new();
+    for relay_address in config.relays {
+        if let Some(listener_id) = connect_relay(&mut swarm, &relay_address) {
+            relays.insert(relay_address, listener_id);
+        }
     }
 
     let mut to_net_rx = to_net_rx;
     loop {
         tokio::select! {
-            clip_msg = to_net_rx.recv() => {
-                if let Some(clip_msg) = clip_msg {
-                    match swarm.behaviour_mut().gossipsub.publish(topic.clone(), clip_msg.encode_to_vec()) {
-                        Ok(_) => {},
-                        Err(err) => {
-                            match err {
-                                PublishError::InsufficientPeers => {},
-                                _ => {panic!("{err}");}
+            command = to_net_rx.recv() => match command {
+                Some(Command::Publish(clip_msg)) => {
+                    let topics: Vec<_> = swarm.behaviour().groups.iter().map(|group| group.topic.clone()).collect();
+                    for topic in &topics {
+                        publish(&mut swarm, &mut mailbox_store, &mailboxes, &versions, &config.device_name, topic, clip_msg.clone());
+                    }
+                }
+                Some(Command::PublishTo { topics, clip_msg }) => {
+                    for topic in &topics {
+                        publish(&mut swarm, &mut mailbox_store, &mailboxes, &versions, &config.device_name, topic, clip_msg.clone());
+                    }
+                }
+                Some(Command::Reconfigure(new_config)) => {
+                    info!(config = ?new_config, "reconfigure");
+                    let behaviour = swarm.behaviour_mut();
+                    let left: Vec<_> = behaviour.groups.iter()
+                        .filter(|group| !new_config.groups.iter().any(|new_group| new_group.topic == group.topic))
+                        .map(|group| group.topic.clone())
+                        .collect();
+                    for topic in &left {
+                        behaviour.leave(topic);
+                    }
+                    for new_group in &new_config.groups {
+                        match behaviour.groups.iter_mut().find(|group| group.topic == new_group.topic) {
+                            Some(group) => group.cipher = new_group.cipher(),
+                            None => {
+                                let index = behaviour.join(new_group);
+                                for peer_id in &mailboxes {
+                                    behaviour.fetch(peer_id, index);
+                                }
                             }
-                        },
+                        }
+                    }
+
+                    relays.retain(|relay_address, listener_id| {
+                        let keep = new_config.relays.contains(relay_address);
+                        if !keep {
+                            info!(%relay_address, "remove relay");
+                            disconnect_relay(&mut swarm, relay_address, *listener_id);
+                        }
+                        keep
+                    });
+                    for relay_address in &new_config.relays {
+                        if !relays.contains_key(relay_address) {
+                            info!(%relay_address, "add relay");
+                            if let Some(listener_id) = connect_relay(&mut swarm, relay_address) {
+                                relays.insert(relay_address.clone(), listener_id);
+                            }
+                        }
                     }
                 }
-            }
+                Some(Command::Sync) => {
+                    let behaviour = swarm.behaviour_mut();
+                    for peer_id in &mailboxes {
+                        for index in 0..behaviour.groups.len() {
+                            behaviour.fetch(peer_id, index);
+                        }
+                    }
+                }
+                Some(Command::Send { to, mut clip_msg, reply_tx }) => {
+                    clip_msg.timestamp.get_or_insert_with(now_millis);
+                    clip_msg.version = PROTOCOL_VERSION;
+                    clip_msg.device.get_or_insert_with(|| config.device_name.clone());
+                    if let Some(peer_version) = versions.get(&to) {
+                        let missing = version::downgrade(&mut clip_msg, &peer_version.capabilities);
+                        if !missing.is_empty() {
+                            warn!(%to, id = clip_msg.id, ?missing, "peer lacks capabilities for this item");
+                        }
+                    }
+                    // sealed for a group both are in, the peer could not open it otherwise
+                    let behaviour = swarm.behaviour_mut();
+                    let shared = behaviour.gossipsub.all_peers()
+                        .find(|(peer_id, _)| **peer_id == to)
+                        .and_then(|(_, topics)| behaviour.groups.iter().find(|group| topics.contains(&&group.hash)));
+                    let group = match shared {
+                        Some(group) => group,
+                        None => {
+                            let _ = reply_tx.send(Err(format!("no group shared with {to}")));
+                            continue;
+                        }
+                    };
+                    let data = group.seal(&clip_msg);
+                    let request_id = behaviour.direct.send_request(&to, data);
+                    pending_sends.insert(request_id, reply_tx);
+                }
+                Some(Command::Peers(reply_tx)) => {
+                    let behaviour = swarm.behaviour();
+                    let peer_info = |peer_id: &PeerId, topics: &[&TopicHash], online| {
+                        let presence = behaviour.presence.get(peer_id);
+                        let groups: Vec<_> = behaviour.groups.iter()
+                            .filter(|group| topics.contains(&&group.hash))
+                            .map(|group| group.topic.clone())
+                            .collect();
+                        PeerInfo {
+                            peer_id: *peer_id,
+                            in_group: !groups.is_empty(),
+                            groups,
+                            mailbox: mailboxes.contains(peer_id),
+                            version: versions.get(peer_id).cloned(),
+                            device: presence.map(|presence| presence.device.clone()),
+                            online,
+                            last_seen: presence.map(|presence| presence.last_seen),
+                        }
+                    };
+                    let mut peers: Vec<_> = behaviour.gossipsub.all_peers()
+                        .map(|(peer_id, topics)| peer_info(peer_id, &topics, true))
+                        .collect();
+                    for peer_id in &mailboxes {
+                        if !peers.iter().any(|peer| peer.peer_id == *peer_id) {
+                            peers.push(peer_info(peer_id, &[], true));
+                        }
+                    }
+                    for peer_id in behaviour.presence.keys() {
+                        if !peers.iter().any(|peer| peer.peer_id == *peer_id) {
+                            peers.push(peer_info(peer_id, &[], swarm.is_connected(peer_id)));
+                        }
+                    }
+                    let _ = reply_tx.send(peers);
+                }
+                None => {
+                    info!("command channel closed, stopping");
+                    return;
+                }
+            },
 
-            event = swarm.select_next_some() => if let SwarmEvent::NewListenAddr { address, .. } =  event {
-                println!("Listening on {:?}", address);
+            event = swarm.select_next_some() => match event {
+                SwarmEvent::NewListenAddr { address, .. } => info!(%address, "listening"),
+                SwarmEvent::ConnectionEstablished { peer_id, endpoint, .. } => {
+                    debug!(%peer_id, address = %endpoint.get_remote_address(), "connection established");
+                    swarm.behaviour_mut().seen(&peer_id);
+                }
+                SwarmEvent::ConnectionClosed { peer_id, cause, num_established, .. } => {
+                    debug!(%peer_id, ?cause, "connection closed");
+                    swarm.behaviour_mut().seen(&peer_id);
+                    if num_established == 0 {
+                        mailboxes.remove(&peer_id);
+                        versions.remove(&peer_id);
+                    }
+                }
+                SwarmEvent::Behaviour(Event::Identify(IdentifyEvent::Received { peer_id, info })) => {
+                    if let Some(peer_version) = PeerVersion::parse(&info.protocol_version) {
+                        if !peer_version.compatible() {
+                            warn!(%peer_id, version = peer_version.version, "peer runs an incompatible protocol version, it needs an update");
+                        } else if peer_version.version > PROTOCOL_VERSION {
+                            info!(%peer_id, version = peer_version.version, "peer runs a newer protocol version");
+                        }
+                        versions.insert(peer_id, peer_version);
+                    }
+                    if let Some(device) = Device::parse(&info.agent_version) {
+                        let presence = Presence { device, last_seen: now_millis() };
+                        if let Some(previous) = swarm.behaviour_mut().presence.insert(peer_id, presence.clone()) {
+                            if previous.device != presence.device {
+                                info!(%peer_id, device = %presence.device, "device changed");
+                            }
+                        } else {
+                            info!(%peer_id, device = %presence.device, "new device");
+                        }
+                    }
+                    // fetch what was missed whenever a mailbox comes back
+                    if info.protocols.iter().any(|protocol| protocol == mailbox::PROTOCOL_NAME)
+                        && mailboxes.insert(peer_id)
+                    {
+                        let behaviour = swarm.behaviour_mut();
+                        for index in 0..behaviour.groups.len() {
+                            behaviour.fetch(&peer_id, index);
+                        }
+                    }
+                }
+                SwarmEvent::Behaviour(Event::Mailbox(RequestResponseEvent::Message { peer, message })) => match message {
+                    RequestResponseMessage::Request { request, channel, .. } => {
+                        if let Some(store) = &mut mailbox_store {
+                            let response = store.handle(request);
+                            if swarm.behaviour_mut().mailbox.send_response(channel, response).is_err() {
+                                debug!(%peer, "mailbox response dropped");
+                            }
+                        }
+                    }
+                    RequestResponseMessage::Response { request_id, mut response } => {
+                        response.items.sort_by_key(|item| item.timestamp);
+                        let behaviour = swarm.behaviour_mut();
+                        // stores are answered without items, fetches of groups we left are dropped
+                        let index = behaviour.fetches.remove(&request_id)
+                            .and_then(|hash| behaviour.groups.iter().position(|group| group.hash == hash));
+                        if let Some(index) = index {
+                            // gossipsub may have delivered some in the meantime
+                            let last_timestamp = behaviour.groups[index].last_timestamp;
+                            let items: Vec<_> = response.items.into_iter()
+                                .filter(|item| item.timestamp > last_timestamp)
+                                .collect();
+                            let count = items.len();
+                            for (n, item) in items.into_iter().enumerate() {
+                                debug!(%peer, id = item.id, "receive from mailbox");
+                                behaviour.receive(index, &item.data, Origin::Mailbox { newest: n + 1 == count });
+                            }
+                        }
+                    }
+                },
+                SwarmEvent::Behaviour(Event::Mailbox(RequestResponseEvent::OutboundFailure { peer, request_id, error })) => {
+                    debug!(%peer, %error, "mailbox request failed");
+                    swarm.behaviour_mut().fetches.remove(&request_id);
+                }
+                SwarmEvent::Behaviour(Event::Direct(RequestResponseEvent::Message { peer, message })) => match message {
+                    RequestResponseMessage::Request { request, channel, .. } => {
+                        let _span = debug_span!("direct", source = %peer).entered();
+                        let behaviour = swarm.behaviour_mut();
+                        behaviour.seen(&peer);
+                        // the sender picked one of the groups, the one whose secret opens it
+                        let opened = (0..behaviour.groups.len())
+                            .find(|index| behaviour.groups[*index].open(&request).is_ok());
+                        let response = match opened {
+                            Some(index) if behaviour.receive(index, &request, Origin::Live) => DirectResponse::Accepted,
+                            _ => {
+                                warn!("drop clip message: no group opens it");
+                                DirectResponse::Rejected
+                            }
+                        };
+                        if behaviour.direct.send_response(channel, response).is_err() {
+                            debug!(%peer, "direct response dropped");
+                        }
+                    }
+                    RequestResponseMessage::Response { request_id, response } => {
+                        if let Some(reply_tx) = pending_sends.remove(&request_id) {
+                            let _ = reply_tx.send(match response {
+                                DirectResponse::Accepted => Ok(()),
+                                DirectResponse::Rejected => Err(format!("{peer} rejected it, secret mismatch?")),
+                            });
+                        }
+                    }
+                },
+                SwarmEvent::Behaviour(Event::Direct(RequestResponseEvent::OutboundFailure { peer, request_id, error })) => {
+                    debug!(%peer, %error, "direct send failed");
+                    if let Some(reply_tx) = pending_sends.remove(&request_id) {
+                        let _ = reply_tx.send(Err(format!("sending to {peer} failed: {error}")));
+                    }
+                }
+                SwarmEvent::OutgoingConnectionError { peer_id, error } => {
+                    warn!(?peer_id, %error, "outgoing connection error");
+                }
+                _ => {}
             }
         }
     }
 }
 
+fn publish(
+    swarm: &mut Swarm<Behaviour>,
+    mailbox_store: &mut Option<Mailbox>,
+    mailboxes: &HashSet<PeerId>,
+    versions: &HashMap<PeerId, PeerVersion>,
+    device_name: &str,
+    topic: &str,
+    mut clip_msg: ClipMsg,
+) {
+    let index = match swarm
+        .behaviour()
+        .groups
+        .iter()
+        .position(|group| group.topic == topic)
+    {
+        Some(index) => index,
+        None => {
+            warn!(%topic, id = clip_msg.id, "not in this group, drop clip message");
+            return;
+        }
+    };
+
+    let timestamp = *clip_msg.timestamp.get_or_insert_with(now_millis);
+    clip_msg.version = PROTOCOL_VERSION;
+    clip_msg
+        .device
+        .get_or_insert_with(|| device_name.to_string());
+    let capabilities = group_capabilities(swarm, &swarm.behaviour().groups[index].hash, versions);
+    let missing = version::downgrade(&mut clip_msg, &capabilities);
+    if !missing.is_empty() {
+        warn!(%topic, id = clip_msg.id, ?missing, "peers in the group lack capabilities for this item");
+    }
+
+    let behaviour = swarm.behaviour_mut();
+    let group = &mut behaviour.groups[index];
+    group.last_timestamp = group.last_timestamp.max(timestamp);
+    let data = group.seal(&clip_msg);
+
+    // also leave it with the mailboxes, gossipsub drops it for offline peers
+    let item = MailboxItem {
+        id: clip_msg.id,
+        timestamp,
+        data: data.clone(),
+    };
+    for peer_id in mailboxes {
+        behaviour
+            .mailbox
+            .send_request(peer_id, mailbox::store_request(topic, item.clone()));
+    }
+    if let Some(store) = mailbox_store {
+        store.handle(mailbox::store_request(topic, item));
+    }
+
+    match behaviour.gossipsub.publish(Topic::new(topic), data) {
+        Ok(_) => {}
+        Err(err) => match err {
+            PublishError::InsufficientPeers => debug!(%topic, "no peers to publish to"),
+            _ => error!(%topic, "publish failed: {err}"),
+        },
+    }
+}
+
+// what every peer of the group that identified itself can take
+fn group_capabilities(
+    swarm: &Swarm<Behaviour>,
+    topic_hash: &TopicHash,
+    versions: &HashMap<PeerId, PeerVersion>,
+) -> BTreeSet<Capability> {
+    let mut capabilities = PeerVersion::local().capabilities;
+    for (peer_id, topics) in swarm.behaviour().gossipsub.all_peers() {
+        if let (true, Some(peer_version)) = (topics.contains(&topic_hash), versions.get(peer_id)) {
+            capabilities.retain(|capability| peer_version.capabilities.contains(capability));
+        }
+    }
+    capabilities
+}
+
 #[derive(NetworkBehaviour)]
 #[behaviour(out_event = "Event")]
 struct Behaviour {
     #[behaviour(event_process = true)]
     gossipsub: Gossipsub,
     #[behaviour(event_process = true)]
-    mdns: Mdns,
+    mdns: Toggle<platform::Discovery>,
     #[behaviour(event_process = false)]
     identify: Identify,
     #[behaviour(event_process = false)]
@@ -210,38 +712,204 @@ struct Behaviour {
 
     relay_client: RelayClient,
     dcutr: DcutrBehaviour,
+    #[behaviour(event_process = false)]
+    mailbox: RequestResponse<MailboxCodec>,
+    #[behaviour(event_process = false)]
+    direct: RequestResponse<DirectCodec>,
 
     #[behaviour(ignore)]
-    from_net_tx: Sender<ClipMsg>,
+    from_net_tx: Sender<Received>,
+    #[behaviour(ignore)]
+    groups: Vec<Group>,
+    // the group each mailbox fetch is for
+    #[behaviour(ignore)]
+    fetches: HashMap<RequestId, TopicHash>,
+    // devices learned through identify
+    #[behaviour(ignore)]
+    presence: HashMap<PeerId, Presence>,
+}
+
+// a topic we're subscribed to
+struct Group {
+    topic: String,
+    hash: TopicHash,
+    cipher: Option<Cipher>,
+    // newest item seen or sent, mailbox fetches start from here
+    last_timestamp: u64,
+}
+
+impl GroupConfig {
+    fn cipher(&self) -> Option<Cipher> {
+        self.secret
+            .as_deref()
+            .map(|secret| Cipher::new(&self.topic, secret))
+    }
+}
+
+impl Group {
+    fn seal(&self, clip_msg: &ClipMsg) -> Vec<u8> {
+        let data = clip_msg.encode_to_vec();
+        match &self.cipher {
+            Some(cipher) => cipher.encrypt(&data),
+            None => data,
+        }
+    }
+
+    fn open(&self, data: &[u8]) -> Result<ClipMsg, DecodeError> {
+        decode(data, self.cipher.as_ref())
+    }
+}
+
+impl Behaviour {
+    // returns the index of the group
+    fn join(&mut self, config: &GroupConfig) -> usize {
+        let topic = Topic::new(config.topic.clone());
+        if let Err(err) = self.gossipsub.subscribe(&topic) {
+            error!("subscribe {topic} failed: {err:?}");
+        }
+        self.groups.push(Group {
+            topic: config.topic.clone(),
+            hash: topic.hash(),
+            cipher: config.cipher(),
+            last_timestamp: 0,
+        });
+        self.groups.len() - 1
+    }
+
+    fn leave(&mut self, topic: &str) {
+        let topic = Topic::new(topic);
+        if let Err(err) = self.gossipsub.unsubscribe(&topic) {
+            warn!("unsubscribe {topic} failed: {err:?}");
+        }
+        self.groups.retain(|group| group.hash != topic.hash());
+    }
+
+    // what a mailbox kept for the group since we last heard of it
+    fn fetch(&mut self, peer_id: &PeerId, index: usize) {
+        let group = &self.groups[index];
+        debug!(%peer_id, topic = %group.topic, since = group.last_timestamp, "fetch from mailbox");
+        let request_id = self.mailbox.send_request(
+            peer_id,
+            mailbox::fetch_request(&group.topic, group.last_timestamp),
+        );
+        self.fetches.insert(request_id, group.hash.clone());
+    }
+
+    fn seen(&mut self, peer_id: &PeerId) {
+        if let Some(presence) = self.presence.get_mut(peer_id) {
+            presence.last_seen = now_millis();
+        }
+    }
+
+    // whether the item was valid for the group
+    fn receive(&mut self, index: usize, data: &[u8], origin: Origin) -> bool {
+        let group = &mut self.groups[index];
+        match group.open(data) {
+            Ok(clip_msg) => {
+                debug!(
+                    id = clip_msg.id,
+                    version = clip_msg.version,
+                    topic = %group.topic,
+                    "receive clip message"
+                );
+                if let Some(timestamp) = clip_msg.timestamp {
+                    group.last_timestamp = group.last_timestamp.max(timestamp);
+                }
+                let received = Received {
+                    topic: group.topic.clone(),
+                    clip_msg,
+                    origin,
+                };
+                if self.from_net_tx.send(received).is_err() {
+                    warn!("receiver stopped, drop clip message");
+                }
+                true
+            }
+            Err(err) => {
+                warn!("drop clip message: {err}");
+                false
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum DecodeError {
+    Decrypt,
+    Decode(prost::DecodeError),
+    Invalid(&'static str),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::Decrypt => write!(f, "undecryptable, secret mismatch?"),
+            DecodeError::Decode(err) => write!(f, "invalid encoding: {err}"),
+            DecodeError::Invalid(reason) => write!(f, "invalid content: {reason}"),
+        }
+    }
+}
+
+/// Turns a payload from the network into an item that is safe to apply.
+pub fn decode(data: &[u8], cipher: Option<&Cipher>) -> Result<ClipMsg, DecodeError> {
+    let clip_msg = match cipher {
+        Some(cipher) => {
+            let data = cipher.decrypt(data).ok_or(DecodeError::Decrypt)?;
+            ClipMsg::decode(data.as_slice())
+        }
+        None => ClipMsg::decode(data),
+    }
+    .map_err(DecodeError::Decode)?;
+    clip_msg.validate().map_err(DecodeError::Invalid)?;
+    Ok(clip_msg)
 }
 
 impl NetworkBehaviourEventProcess<GossipsubEvent> for Behaviour {
     fn inject_event(&mut self, message: GossipsubEvent) {
         if let GossipsubEvent::Message {
-            propagation_source: _,
-            message_id: _,
+            propagation_source,
+            message_id,
             message,
         } = message
         {
-            if let Ok(clip_msg) = ClipMsg::decode(message.data.as_slice()) {
-                self.from_net_tx.send(clip_msg).unwrap();
+            let _span =
+                debug_span!("gossipsub", source = %propagation_source, id = %message_id).entered();
+            // the author, which is not necessarily the peer that forwarded it
+            if let Some(author) = &message.source {
+                self.seen(author);
+            }
+            match self
+                .groups
+                .iter()
+                .position(|group| group.hash == message.topic)
+            {
+                Some(index) => {
+                    self.receive(index, &message.data, Origin::Live);
+                }
+                None => debug!(topic = %message.topic, "not in this group, drop clip message"),
             }
         }
     }
 }
 
+#[cfg(not(target_arch = "wasm32"))]
 impl NetworkBehaviourEventProcess<MdnsEvent> for Behaviour {
     fn inject_event(&mut self, event: MdnsEvent) {
         match event {
             MdnsEvent::Discovered(list) => {
                 for (peer, addr) in list {
-                    println!("new peer: {peer} - {addr}");
+                    info!(%peer, %addr, "new peer");
                     self.gossipsub.add_explicit_peer(&peer);
                 }
             }
             MdnsEvent::Expired(list) => {
                 for (peer, _) in list {
-                    if !self.mdns.has_node(&peer) {
+                    if !self
+                        .mdns
+                        .as_ref()
+                        .map_or(false, |mdns| mdns.has_node(&peer))
+                    {
+                        debug!(%peer, "peer expired");
                         self.gossipsub.remove_explicit_peer(&peer);
                     }
                 }
@@ -250,14 +918,25 @@ impl NetworkBehaviourEventProcess<MdnsEvent> for Behaviour {
     }
 }
 
+// no local discovery in the browser
+#[cfg(target_arch = "wasm32")]
+impl NetworkBehaviourEventProcess<void::Void> for Behaviour {
+    fn inject_event(&mut self, event: void::Void) {
+        void::unreachable(event)
+    }
+}
+
 #[derive(Debug)]
 enum Event {
     AutoNat(autonat::Event),
     Identify(IdentifyEvent),
+    #[cfg(not(target_arch = "wasm32"))]
     Mdns(MdnsEvent),
     Gossipsub(GossipsubEvent),
     Relay(RelayEvent),
     Dcutr(DcutrEvent),
+    Mailbox(RequestResponseEvent<mailbox::MailboxRequest, mailbox::MailboxResponse>),
+    Direct(RequestResponseEvent<Vec<u8>, DirectResponse>),
 }
 
 impl From<autonat::Event> for Event {
@@ -272,6 +951,7 @@ impl From<IdentifyEvent> for Event {
     }
 }
 
+#[cfg(not(target_arch = "wasm32"))]
 impl From<MdnsEvent> for Event {
     fn from(v: MdnsEvent) -> Self {
         Self::Mdns(v)
@@ -295,3 +975,15 @@ impl From<dcutr::behaviour::Event> for Event {
         Event::Dcutr(e)
     }
 }
+
+impl From<RequestResponseEvent<mailbox::MailboxRequest, mailbox::MailboxResponse>> for Event {
+    fn from(e: RequestResponseEvent<mailbox::MailboxRequest, mailbox::MailboxResponse>) -> Self {
+        Event::Mailbox(e)
+    }
+}
+
+impl From<RequestResponseEvent<Vec<u8>, DirectResponse>> for Event {
+    fn from(e: RequestResponseEvent<Vec<u8>, DirectResponse>) -> Self {
+        Event::Direct(e)
+    }
+}
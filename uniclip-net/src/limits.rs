@@ -0,0 +1,38 @@
+use serde::{Deserialize, Serialize};
+use uniclip_proto::{clip_msg::MsgType, ClipMsg};
+
+/// Sizes of items a device publishes and applies, the same for desktop and Android.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Limits {
+    // bytes
+    pub text: usize,
+    pub image: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            text: 1024 * 1024,
+            image: 1024 * 1024 * 32,
+        }
+    }
+}
+
+impl Limits {
+    pub fn accepts(&self, msg: &ClipMsg) -> Result<(), &'static str> {
+        match msg.typ() {
+            MsgType::Text if msg.text().len() > self.text => Err("text too large"),
+            MsgType::Image => match &msg.image {
+                Some(image) if image.data.len() > self.image => Err("image too large"),
+                _ => Ok(()),
+            },
+            _ => Ok(()),
+        }
+    }
+
+    /// For `max_message_size`.
+    pub fn largest(&self) -> usize {
+        self.text.max(self.image)
+    }
+}
@@ -0,0 +1,372 @@
+use crate::MAX_MESSAGE_SIZE;
+use async_trait::async_trait;
+use futures::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use libp2p::{
+    core::{
+        upgrade::{read_length_prefixed, write_length_prefixed},
+        ProtocolName,
+    },
+    request_response::RequestResponseCodec,
+};
+use prost::Message;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{HashMap, VecDeque},
+    io,
+    time::{Duration, Instant},
+};
+
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub use uniclip_proto::{mailbox_request::Kind, MailboxItem, MailboxRequest, MailboxResponse};
+
+pub const PROTOCOL_NAME: &str = "/uniclip/mailbox/1.0.0";
+
+// sha256
+const GROUP_LEN: usize = 32;
+
+#[derive(Debug, Clone)]
+pub struct MailboxConfig {
+    // items kept per group
+    pub size: usize,
+    pub ttl: Duration,
+    // anyone can store, these bound the memory; the oldest items go first
+    pub groups: usize,
+    pub bytes: usize,
+}
+
+impl Default for MailboxConfig {
+    fn default() -> Self {
+        Self {
+            size: 16,
+            ttl: Duration::from_secs(60 * 60 * 24),
+            groups: 1024,
+            bytes: 256 * 1024 * 1024,
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+// SystemTime panics on wasm32-unknown-unknown
+#[cfg(target_arch = "wasm32")]
+pub fn now_millis() -> u64 {
+    js_sys::Date::now() as u64
+}
+
+// mailboxes key items by a hash so they never learn the topic
+pub fn group(topic: &str) -> Vec<u8> {
+    Sha256::new()
+        .chain_update(b"uniclip-mailbox")
+        .chain_update(topic.as_bytes())
+        .finalize()
+        .to_vec()
+}
+
+pub fn store_request(topic: &str, item: MailboxItem) -> MailboxRequest {
+    MailboxRequest {
+        group: group(topic),
+        kind: Some(Kind::Store(item)),
+    }
+}
+
+pub fn fetch_request(topic: &str, since: u64) -> MailboxRequest {
+    MailboxRequest {
+        group: group(topic),
+        kind: Some(Kind::FetchSince(since)),
+    }
+}
+
+// the role of an always-on node, keeps the latest items of every group
+pub struct Mailbox {
+    config: MailboxConfig,
+    groups: HashMap<Vec<u8>, VecDeque<(Instant, MailboxItem)>>,
+    // of the item data
+    bytes: usize,
+}
+
+impl Mailbox {
+    pub fn new(config: MailboxConfig) -> Self {
+        Self {
+            config,
+            groups: HashMap::new(),
+            bytes: 0,
+        }
+    }
+
+    pub fn handle(&mut self, request: MailboxRequest) -> MailboxResponse {
+        self.expire();
+        // anything else is not from `group` and would only take up memory
+        if request.group.len() != GROUP_LEN {
+            return MailboxResponse::default();
+        }
+        match request.kind {
+            Some(Kind::Store(item)) => {
+                self.store(request.group, item);
+                MailboxResponse::default()
+            }
+            Some(Kind::FetchSince(since)) => MailboxResponse {
+                items: self.fetch(&request.group, since),
+            },
+            None => MailboxResponse::default(),
+        }
+    }
+
+    fn store(&mut self, group: Vec<u8>, mut item: MailboxItem) {
+        if self.config.size == 0 || self.config.groups == 0 || item.data.len() > self.config.bytes {
+            return;
+        }
+        // one dated in the future would sort last and push the others out
+        item.timestamp = item.timestamp.min(now_millis());
+        let items = self.groups.entry(group).or_default();
+        if items.iter().any(|(_, stored)| stored.id == item.id) {
+            return;
+        }
+
+        // ordered by timestamp, oldest first
+        let position = items
+            .iter()
+            .rposition(|(_, stored)| stored.timestamp <= item.timestamp)
+            .map_or(0, |position| position + 1);
+        self.bytes += item.data.len();
+        items.insert(position, (Instant::now(), item));
+        while items.len() > self.config.size {
+            if let Some((_, item)) = items.pop_front() {
+                self.bytes -= item.data.len();
+            }
+        }
+
+        while self.groups.len() > self.config.groups || self.bytes > self.config.bytes {
+            self.evict_oldest();
+        }
+    }
+
+    // the item stored first, across all groups
+    fn evict_oldest(&mut self) {
+        let oldest = self
+            .groups
+            .iter()
+            .flat_map(|(group, items)| {
+                items
+                    .iter()
+                    .enumerate()
+                    .map(move |(index, (stored, _))| (*stored, group, index))
+            })
+            .min_by_key(|(stored, _, _)| *stored)
+            .map(|(_, group, index)| (group.clone(), index));
+        let (group, index) = match oldest {
+            Some(oldest) => oldest,
+            None => return,
+        };
+        if let Some(items) = self.groups.get_mut(&group) {
+            if let Some((_, item)) = items.remove(index) {
+                self.bytes -= item.data.len();
+            }
+            if items.is_empty() {
+                self.groups.remove(&group);
+            }
+        }
+    }
+
+    // the newest items that fit into one message, oldest first
+    fn fetch(&self, group: &[u8], since: u64) -> Vec<MailboxItem> {
+        let budget = MAX_MESSAGE_SIZE - crate::max_message_size(0);
+        let mut bytes = 0;
+        let mut items: Vec<_> = self
+            .groups
+            .get(group)
+            .into_iter()
+            .flat_map(|items| items.iter().rev())
+            .filter(|(_, item)| item.timestamp > since)
+            .take_while(|(_, item)| {
+                bytes += item.data.len();
+                bytes <= budget
+            })
+            .map(|(_, item)| item.clone())
+            .collect();
+        items.reverse();
+        items
+    }
+
+    fn expire(&mut self) {
+        let ttl = self.config.ttl;
+        let bytes = &mut self.bytes;
+        self.groups.retain(|_, items| {
+            items.retain(|(stored, item)| {
+                let keep = stored.elapsed() < ttl;
+                if !keep {
+                    *bytes -= item.data.len();
+                }
+                keep
+            });
+            !items.is_empty()
+        });
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MailboxProtocol;
+
+impl ProtocolName for MailboxProtocol {
+    fn protocol_name(&self) -> &[u8] {
+        PROTOCOL_NAME.as_bytes()
+    }
+}
+
+// a store carries one sealed item, a fetch response as many as fit, see `Mailbox::fetch`
+#[derive(Debug, Clone)]
+pub struct MailboxCodec {
+    max_message_size: usize,
+}
+
+impl MailboxCodec {
+    pub fn new(max_message_size: usize) -> Self {
+        Self { max_message_size }
+    }
+}
+
+#[async_trait]
+impl RequestResponseCodec for MailboxCodec {
+    type Protocol = MailboxProtocol;
+    type Request = MailboxRequest;
+    type Response = MailboxResponse;
+
+    async fn read_request<T>(
+        &mut self,
+        _: &MailboxProtocol,
+        io: &mut T,
+    ) -> io::Result<Self::Request>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        let data = read_length_prefixed(io, self.max_message_size).await?;
+        MailboxRequest::decode(data.as_slice())
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+
+    async fn read_response<T>(
+        &mut self,
+        _: &MailboxProtocol,
+        io: &mut T,
+    ) -> io::Result<Self::Response>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        let data = read_length_prefixed(io, self.max_message_size).await?;
+        MailboxResponse::decode(data.as_slice())
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+
+    async fn write_request<T>(
+        &mut self,
+        _: &MailboxProtocol,
+        io: &mut T,
+        request: Self::Request,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        write_length_prefixed(io, request.encode_to_vec()).await?;
+        io.close().await
+    }
+
+    async fn write_response<T>(
+        &mut self,
+        _: &MailboxProtocol,
+        io: &mut T,
+        response: Self::Response,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        write_length_prefixed(io, response.encode_to_vec()).await?;
+        io.close().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(id: u32, timestamp: u64, len: usize) -> MailboxItem {
+        MailboxItem {
+            id,
+            timestamp,
+            data: vec![0; len],
+        }
+    }
+
+    fn store(mailbox: &mut Mailbox, group: u8, item: MailboxItem) {
+        mailbox.handle(MailboxRequest {
+            group: vec![group; GROUP_LEN],
+            kind: Some(Kind::Store(item)),
+        });
+    }
+
+    fn fetch(mailbox: &mut Mailbox, group: u8) -> Vec<u32> {
+        mailbox
+            .handle(MailboxRequest {
+                group: vec![group; GROUP_LEN],
+                kind: Some(Kind::FetchSince(0)),
+            })
+            .items
+            .iter()
+            .map(|item| item.id)
+            .collect()
+    }
+
+    #[test]
+    fn groups_are_capped_oldest_first() {
+        let mut mailbox = Mailbox::new(MailboxConfig {
+            groups: 2,
+            ..Default::default()
+        });
+        for group in 0..3 {
+            store(&mut mailbox, group, item(group as u32 + 1, 1, 8));
+        }
+        assert_eq!(mailbox.groups.len(), 2);
+        assert!(fetch(&mut mailbox, 0).is_empty());
+        assert_eq!(fetch(&mut mailbox, 2), vec![3]);
+    }
+
+    #[test]
+    fn bytes_are_capped_oldest_first() {
+        let mut mailbox = Mailbox::new(MailboxConfig {
+            bytes: 100,
+            ..Default::default()
+        });
+        store(&mut mailbox, 0, item(1, 1, 40));
+        store(&mut mailbox, 1, item(2, 2, 40));
+        store(&mut mailbox, 0, item(3, 3, 40));
+        assert_eq!(mailbox.bytes, 80);
+        assert_eq!(fetch(&mut mailbox, 0), vec![3]);
+        assert_eq!(fetch(&mut mailbox, 1), vec![2]);
+
+        // larger than the whole mailbox
+        store(&mut mailbox, 2, item(4, 4, 101));
+        assert!(fetch(&mut mailbox, 2).is_empty());
+        assert_eq!(mailbox.bytes, 80);
+    }
+
+    #[test]
+    fn future_timestamps_are_clamped() {
+        let mut mailbox = Mailbox::new(MailboxConfig {
+            size: 2,
+            ..Default::default()
+        });
+        store(&mut mailbox, 0, item(1, u64::MAX, 8));
+        store(&mut mailbox, 0, item(2, u64::MAX / 2, 8));
+        store(&mut mailbox, 0, item(3, now_millis(), 8));
+        // the future ones sort before it now, so the real item stays
+        assert_eq!(fetch(&mut mailbox, 0), vec![2, 3]);
+        let items = mailbox.groups.get([0; GROUP_LEN].as_slice()).unwrap();
+        assert!(items.iter().all(|(_, item)| item.timestamp <= now_millis()));
+    }
+}
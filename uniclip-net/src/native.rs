@@ -0,0 +1,80 @@
+use crate::Config;
+use futures::{executor::block_on, Future};
+use libp2p::{
+    core::{
+        muxing::StreamMuxerBox,
+        transport::{Boxed, OrTransport},
+        upgrade,
+    },
+    dns::DnsConfig,
+    identity::Keypair,
+    mdns::Mdns,
+    mplex::MplexConfig,
+    noise,
+    relay::v2::client::transport::ClientTransport,
+    tcp::TcpConfig,
+    PeerId, Transport,
+};
+use std::{
+    fs,
+    io::{Read, Write},
+    pin::Pin,
+};
+use tracing::{info, warn};
+
+pub type Discovery = Mdns;
+
+pub fn load_keypair(config: &Config) -> Keypair {
+    let filepath = config.dir.join("keypair");
+
+    match fs::File::open(&filepath) {
+        Ok(mut file) => {
+            let mut buffer = vec![0; file.metadata().unwrap().len() as usize];
+            file.read(&mut buffer).expect("buffer overflow");
+            Keypair::from_protobuf_encoding(&buffer).unwrap()
+        }
+        Err(_) => {
+            let keypair = Keypair::generate_ed25519();
+            let buffer = keypair.to_protobuf_encoding().unwrap();
+            let saved = fs::create_dir_all(&config.dir)
+                .and_then(|_| fs::File::create(&filepath))
+                .and_then(|mut file| file.write_all(&buffer));
+            match saved {
+                Ok(_) => info!(path = %filepath.display(), "new keypair saved"),
+                Err(err) => warn!(
+                    path = %filepath.display(),
+                    "saving keypair failed, peer id will change on restart: {err}"
+                ),
+            }
+            keypair
+        }
+    }
+}
+
+pub fn transport(
+    local_key: &Keypair,
+    relay_transport: ClientTransport,
+) -> Boxed<(PeerId, StreamMuxerBox)> {
+    let noise_keys = noise::Keypair::<noise::X25519Spec>::new()
+        .into_authentic(local_key)
+        .expect("Signing libp2p-noise static DH keypair failed.");
+
+    OrTransport::new(
+        block_on(DnsConfig::system(TcpConfig::new().port_reuse(true))).unwrap(),
+        relay_transport,
+    )
+    .upgrade(upgrade::Version::V1)
+    .authenticate(noise::NoiseConfig::xx(noise_keys).into_authenticated())
+    .multiplex(MplexConfig::new())
+    .boxed()
+}
+
+pub async fn discovery() -> Discovery {
+    Mdns::new(Default::default()).await.unwrap()
+}
+
+// We want the connection background tasks to be spawned
+// onto the tokio runtime.
+pub fn spawn(fut: Pin<Box<dyn Future<Output = ()> + Send>>) {
+    tokio::spawn(fut);
+}
@@ -0,0 +1,61 @@
+use std::fmt;
+
+const AGENT: &str = "uniclip/";
+
+/// What a node tells about itself in identify's agent version,
+/// e.g. `uniclip/0.1.0 linux Alice's laptop`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Device {
+    pub name: String,
+    pub os: String,
+    pub version: String,
+}
+
+impl Device {
+    pub fn local(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            os: os().to_string(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+        }
+    }
+
+    pub fn to_agent_version(&self) -> String {
+        format!("{AGENT}{} {} {}", self.version, self.os, self.name)
+    }
+
+    /// None for peers that aren't uniclip nodes, like relays.
+    pub fn parse(agent_version: &str) -> Option<Self> {
+        let mut parts = agent_version.strip_prefix(AGENT)?.splitn(3, ' ');
+        Some(Self {
+            version: parts.next()?.to_string(),
+            os: parts.next().unwrap_or_default().to_string(),
+            // names may contain spaces
+            name: parts.next().unwrap_or_default().to_string(),
+        })
+    }
+}
+
+impl fmt::Display for Device {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({}, {})", self.name, self.os, self.version)
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn os() -> &'static str {
+    std::env::consts::OS
+}
+
+#[cfg(target_arch = "wasm32")]
+fn os() -> &'static str {
+    "browser"
+}
+
+/// A device we've heard from, kept after it disconnected.
+#[derive(Debug, Clone)]
+pub struct Presence {
+    pub device: Device,
+    // milliseconds since the unix epoch
+    pub last_seen: u64,
+}
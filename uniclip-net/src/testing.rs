@@ -0,0 +1,109 @@
+//! Nodes running in one process over libp2p's memory transport, for tests.
+
+use crate::{Command, Config, Node, PeerInfo, Received};
+use libp2p::{
+    core::{transport::MemoryTransport, upgrade},
+    identity::Keypair,
+    mplex::MplexConfig,
+    multiaddr::Protocol,
+    noise,
+    relay::v2::client::Client as RelayClient,
+    Multiaddr, PeerId, Transport,
+};
+use std::{
+    sync::mpsc::{self, Receiver},
+    thread,
+    time::{Duration, Instant},
+};
+use tokio::sync::mpsc::Sender;
+
+pub struct TestNode {
+    pub peer_id: PeerId,
+    pub address: Multiaddr,
+    pub to_net_tx: Sender<Command>,
+    pub from_net_rx: Receiver<Received>,
+}
+
+impl TestNode {
+    /// Starts a node on its own runtime thread, without mdns or relays,
+    /// connected to the nodes at `dial`.
+    pub fn spawn(config: Config, dial: &[Multiaddr]) -> Self {
+        let local_key = Keypair::generate_ed25519();
+        let peer_id = PeerId::from(local_key.public());
+        let address = Multiaddr::empty().with(Protocol::Memory(rand::random::<u64>() | 1));
+
+        let noise_keys = noise::Keypair::<noise::X25519Spec>::new()
+            .into_authentic(&local_key)
+            .expect("Signing libp2p-noise static DH keypair failed.");
+        let (relay_transport, relay_client) = RelayClient::new_transport_and_behaviour(peer_id);
+        let transport = MemoryTransport::default()
+            .or_transport(relay_transport)
+            .upgrade(upgrade::Version::V1)
+            .authenticate(noise::NoiseConfig::xx(noise_keys).into_authenticated())
+            .multiplex(MplexConfig::new())
+            .boxed();
+
+        let node = Node {
+            local_key,
+            transport,
+            relay_client,
+            discovery: None,
+            dial: dial.to_vec(),
+        };
+        let config = Config {
+            listen_addrs: vec![address.clone()],
+            relays: Vec::new(),
+            ..config
+        };
+
+        let (from_net_tx, from_net_rx) = mpsc::channel();
+        let (to_net_tx, to_net_rx) = tokio::sync::mpsc::channel(10);
+        thread::spawn(move || {
+            tokio::runtime::Builder::new_multi_thread()
+                .enable_all()
+                .build()
+                .unwrap()
+                .block_on(crate::run(node, config, from_net_tx, to_net_rx));
+        });
+
+        Self {
+            peer_id,
+            address,
+            to_net_tx,
+            from_net_rx,
+        }
+    }
+
+    pub fn peers(&self) -> Vec<PeerInfo> {
+        let (reply_tx, reply_rx) = tokio::sync::oneshot::channel();
+        self.to_net_tx
+            .blocking_send(Command::Peers(reply_tx))
+            .expect("node stopped");
+        reply_rx.blocking_recv().expect("node stopped")
+    }
+}
+
+/// Starts `configs.len()` nodes, each connected to all nodes before it.
+pub fn spawn_network(configs: Vec<Config>) -> Vec<TestNode> {
+    let mut nodes: Vec<TestNode> = Vec::new();
+    for config in configs {
+        let dial: Vec<_> = nodes.iter().map(|node| node.address.clone()).collect();
+        nodes.push(TestNode::spawn(config, &dial));
+    }
+    nodes
+}
+
+/// Waits until every node sees `expected` peers subscribed to its topic.
+pub fn wait_for_group(nodes: &[TestNode], expected: usize, timeout: Duration) -> bool {
+    let deadline = Instant::now() + timeout;
+    while Instant::now() < deadline {
+        if nodes
+            .iter()
+            .all(|node| node.peers().iter().filter(|peer| peer.in_group).count() >= expected)
+        {
+            return true;
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    false
+}
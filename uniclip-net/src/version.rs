@@ -0,0 +1,166 @@
+use std::{collections::BTreeSet, fmt};
+use uniclip_proto::{clip_msg::MsgType, ClipMsg};
+
+/// Sent in every message, bump it when receivers need to know about a change.
+/// Messages without one come from peers that predate versioning, version 0.
+pub const PROTOCOL_VERSION: u32 = 1;
+/// Peers below this can't take part in the group anymore. Version 0 peers derive
+/// another key and don't validate items, so they can't read what this version sends.
+pub const MIN_COMPATIBLE_VERSION: u32 = 1;
+
+const PREFIX: &str = "/uniclip/";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Capability {
+    Text,
+    Image,
+    // clears items again after `ClipMsg.ttl`
+    Ttl,
+}
+
+impl Capability {
+    pub const ALL: [Capability; 3] = [Capability::Text, Capability::Image, Capability::Ttl];
+
+    fn name(&self) -> &'static str {
+        match self {
+            Capability::Text => "text",
+            Capability::Image => "image",
+            Capability::Ttl => "ttl",
+        }
+    }
+
+    fn parse(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|capability| capability.name() == name)
+    }
+}
+
+impl fmt::Display for Capability {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// What a peer advertises in identify's protocol version,
+/// e.g. `/uniclip/1 text image ttl`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeerVersion {
+    pub version: u32,
+    pub capabilities: BTreeSet<Capability>,
+}
+
+impl PeerVersion {
+    pub fn local() -> Self {
+        Self {
+            version: PROTOCOL_VERSION,
+            capabilities: Capability::ALL.into_iter().collect(),
+        }
+    }
+
+    pub fn to_protocol_version(&self) -> String {
+        let mut protocol_version = format!("{PREFIX}{}", self.version);
+        for capability in &self.capabilities {
+            protocol_version.push(' ');
+            protocol_version.push_str(capability.name());
+        }
+        protocol_version
+    }
+
+    /// None for peers that aren't uniclip nodes.
+    pub fn parse(protocol_version: &str) -> Option<Self> {
+        let mut parts = protocol_version.strip_prefix(PREFIX)?.split_whitespace();
+        match parts.next()?.parse() {
+            Ok(version) => Some(Self {
+                version,
+                // unknown names come from newer peers, we can't use them anyway
+                capabilities: parts.filter_map(Capability::parse).collect(),
+            }),
+            // `/uniclip/0.1.0` of the first releases, which only knew text and images
+            Err(_) => Some(Self {
+                version: 0,
+                capabilities: [Capability::Text, Capability::Image].into_iter().collect(),
+            }),
+        }
+    }
+
+    pub fn compatible(&self) -> bool {
+        self.version >= MIN_COMPATIBLE_VERSION
+    }
+}
+
+/// Strips what not every receiver can take, as gossipsub sends one message to the whole group.
+/// Returns the capabilities that were missing for the item.
+pub fn downgrade(msg: &mut ClipMsg, capabilities: &BTreeSet<Capability>) -> Vec<Capability> {
+    let mut missing = Vec::new();
+    if msg.typ() == MsgType::Image && !capabilities.contains(&Capability::Image) {
+        missing.push(Capability::Image);
+        // images copied from a browser often come with a text alternative
+        if msg.text.is_some() {
+            msg.image = None;
+            msg.set_typ(MsgType::Text);
+        }
+    }
+    if msg.ttl.is_some() && !capabilities.contains(&Capability::Ttl) {
+        missing.push(Capability::Ttl);
+    }
+    missing
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn capabilities(capabilities: &[Capability]) -> BTreeSet<Capability> {
+        capabilities.iter().copied().collect()
+    }
+
+    #[test]
+    fn parse_round_trips_the_local_version() {
+        let local = PeerVersion::local();
+        assert_eq!(
+            PeerVersion::parse(&local.to_protocol_version()),
+            Some(local)
+        );
+    }
+
+    #[test]
+    fn parse_protocol_versions() {
+        let cases = [
+            ("/ipfs/0.1.0", None),
+            ("", None),
+            ("/uniclip/", None),
+            (
+                "/uniclip/0.1.0",
+                Some((0, capabilities(&[Capability::Text, Capability::Image]))),
+            ),
+            ("/uniclip/1", Some((1, capabilities(&[])))),
+            (
+                "/uniclip/1 text ttl",
+                Some((1, capabilities(&[Capability::Text, Capability::Ttl]))),
+            ),
+            (
+                "/uniclip/7 text hologram",
+                Some((7, capabilities(&[Capability::Text]))),
+            ),
+        ];
+        for (protocol_version, expected) in cases {
+            let parsed = PeerVersion::parse(protocol_version)
+                .map(|version| (version.version, version.capabilities));
+            assert_eq!(parsed, expected, "{protocol_version:?}");
+        }
+    }
+
+    #[test]
+    fn first_releases_are_incompatible() {
+        let first = PeerVersion::parse("/uniclip/0.1.0").unwrap();
+        assert!(!first.compatible());
+        assert!(PeerVersion::local().compatible());
+
+        let newer = PeerVersion {
+            version: PROTOCOL_VERSION + 1,
+            capabilities: BTreeSet::new(),
+        };
+        assert!(newer.compatible());
+    }
+}
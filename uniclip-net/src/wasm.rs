@@ -0,0 +1,81 @@
+use crate::Config;
+use futures::Future;
+use libp2p::{
+    core::{
+        muxing::StreamMuxerBox,
+        transport::{Boxed, OrTransport},
+        upgrade,
+    },
+    identity::Keypair,
+    mplex::MplexConfig,
+    noise,
+    relay::v2::client::transport::ClientTransport,
+    swarm::DummyBehaviour,
+    wasm_ext::{ffi, ExtTransport},
+    PeerId, Transport,
+};
+use std::pin::Pin;
+use tracing::{info, warn};
+use web_sys::Storage;
+
+const KEYPAIR_KEY: &str = "uniclip-keypair";
+
+// browsers have no local network discovery
+pub type Discovery = DummyBehaviour;
+
+// kept hex encoded in localStorage, without it every page load is a new peer
+pub fn load_keypair(_config: &Config) -> Keypair {
+    let storage = web_sys::window().and_then(|window| window.local_storage().ok().flatten());
+    if let Some(keypair) = storage.as_ref().and_then(stored_keypair) {
+        return keypair;
+    }
+
+    let keypair = Keypair::generate_ed25519();
+    let encoded: String = keypair
+        .to_protobuf_encoding()
+        .unwrap()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect();
+    match storage.map(|storage| storage.set_item(KEYPAIR_KEY, &encoded)) {
+        Some(Ok(())) => info!("new keypair saved"),
+        _ => warn!("saving keypair failed, peer id will change on reload"),
+    }
+    keypair
+}
+
+fn stored_keypair(storage: &Storage) -> Option<Keypair> {
+    let encoded = storage.get_item(KEYPAIR_KEY).ok().flatten()?;
+    let bytes = (0..encoded.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(encoded.get(index..index + 2)?, 16).ok())
+        .collect::<Option<Vec<u8>>>()?;
+    Keypair::from_protobuf_encoding(&bytes).ok()
+}
+
+// websocket only, so relays need a /ws or /wss address
+pub fn transport(
+    local_key: &Keypair,
+    relay_transport: ClientTransport,
+) -> Boxed<(PeerId, StreamMuxerBox)> {
+    let noise_keys = noise::Keypair::<noise::X25519Spec>::new()
+        .into_authentic(local_key)
+        .expect("Signing libp2p-noise static DH keypair failed.");
+
+    OrTransport::new(
+        ExtTransport::new(ffi::websocket_transport()),
+        relay_transport,
+    )
+    .upgrade(upgrade::Version::V1)
+    .authenticate(noise::NoiseConfig::xx(noise_keys).into_authenticated())
+    .multiplex(MplexConfig::new())
+    .boxed()
+}
+
+pub async fn discovery() -> Discovery {
+    DummyBehaviour::default()
+}
+
+pub fn spawn(fut: Pin<Box<dyn Future<Output = ()> + Send>>) {
+    wasm_bindgen_futures::spawn_local(fut);
+}
@@ -1 +1,25 @@
 include!(concat!(env!("OUT_DIR"), "/msg.rs"));
+
+use clip_msg::MsgType;
+
+impl ClipMsg {
+    /// Checks what receivers rely on before applying an item, peers may send anything.
+    pub fn validate(&self) -> Result<(), &'static str> {
+        let typ = MsgType::from_i32(self.typ).ok_or("unknown type")?;
+        match (typ, &self.image) {
+            (MsgType::Image, None) => Err("image without data"),
+            (MsgType::Image, Some(image)) => {
+                if image.width == 0 || image.height == 0 {
+                    return Err("empty image");
+                }
+                // rgba
+                let len = image.width as u64 * image.height as u64 * 4;
+                if image.data.len() as u64 != len {
+                    return Err("image size mismatch");
+                }
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,108 @@
+use proptest::prelude::*;
+use prost::Message;
+use uniclip_proto::{
+    clip_msg::{ImageData, MsgType},
+    mailbox_request::Kind,
+    ClipMsg, MailboxItem, MailboxRequest, MailboxResponse,
+};
+
+fn image() -> impl Strategy<Value = ImageData> {
+    (0u32..16, 0u32..16)
+        .prop_flat_map(|(width, height)| {
+            let len = (width * height * 4) as usize;
+            (
+                prop::collection::vec(any::<u8>(), len..=len + 1),
+                Just(width),
+                Just(height),
+            )
+        })
+        .prop_map(|(data, width, height)| ImageData {
+            data,
+            width,
+            height,
+        })
+}
+
+fn clip_msg() -> impl Strategy<Value = ClipMsg> {
+    (
+        any::<u32>(),
+        0i32..4,
+        prop::option::of(".*"),
+        prop::option::of(image()),
+        prop::option::of(any::<u32>()),
+        prop::option::of(any::<u64>()),
+        any::<u32>(),
+        prop::option::of(".*"),
+    )
+        .prop_map(
+            |(id, typ, text, image, ttl, timestamp, version, device)| ClipMsg {
+                id,
+                typ,
+                text,
+                image,
+                ttl,
+                timestamp,
+                version,
+                device,
+            },
+        )
+}
+
+fn mailbox_item() -> impl Strategy<Value = MailboxItem> {
+    (
+        any::<u32>(),
+        any::<u64>(),
+        prop::collection::vec(any::<u8>(), 0..64),
+    )
+        .prop_map(|(id, timestamp, data)| MailboxItem {
+            id,
+            timestamp,
+            data,
+        })
+}
+
+proptest! {
+    #[test]
+    fn clip_msg_roundtrips(msg in clip_msg()) {
+        let decoded = ClipMsg::decode(msg.encode_to_vec().as_slice()).unwrap();
+        prop_assert_eq!(decoded, msg);
+    }
+
+    #[test]
+    fn valid_images_match_their_dimensions(msg in clip_msg()) {
+        if msg.validate().is_ok() && msg.typ() == MsgType::Image {
+            let image = msg.image.unwrap();
+            prop_assert!(image.width > 0 && image.height > 0);
+            prop_assert_eq!(image.data.len(), (image.width * image.height * 4) as usize);
+        }
+    }
+
+    #[test]
+    fn arbitrary_bytes_never_panic(data in prop::collection::vec(any::<u8>(), 0..256)) {
+        if let Ok(msg) = ClipMsg::decode(data.as_slice()) {
+            let _ = msg.validate();
+        }
+        let _ = MailboxRequest::decode(data.as_slice());
+        let _ = MailboxResponse::decode(data.as_slice());
+    }
+
+    #[test]
+    fn mailbox_request_roundtrips(
+        group in prop::collection::vec(any::<u8>(), 0..40),
+        kind in prop::option::of(prop_oneof![
+            mailbox_item().prop_map(Kind::Store),
+            any::<u64>().prop_map(Kind::FetchSince),
+        ]),
+    ) {
+        let request = MailboxRequest { group, kind };
+        let decoded = MailboxRequest::decode(request.encode_to_vec().as_slice()).unwrap();
+        prop_assert_eq!(decoded, request);
+    }
+}
+
+#[test]
+fn image_without_data_is_invalid() {
+    let mut msg = ClipMsg::default();
+    msg.set_typ(MsgType::Image);
+    assert!(msg.validate().is_err());
+}
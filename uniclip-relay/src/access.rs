@@ -0,0 +1,102 @@
+use libp2p::{relay::v2::relay::rate_limiter::RateLimiter, Multiaddr, PeerId};
+use prometheus_client::{
+    encoding::text::Encode,
+    metrics::{counter::Counter, family::Family},
+};
+use serde::Deserialize;
+use std::{collections::HashSet, sync::Arc, time::Instant};
+use tracing::warn;
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AccessConfig {
+    // peers allowed to reserve and open circuits, everyone when empty; others can
+    // still connect for identify and autonat
+    pub allowed_peers: Vec<String>,
+    // rendezvous namespaces peers may register in, all when empty
+    pub allowed_namespaces: Vec<String>,
+}
+
+#[derive(Clone, Hash, PartialEq, Eq, Encode)]
+pub struct DenialLabels {
+    kind: Denied,
+}
+
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, Encode)]
+pub enum Denied {
+    Reservation,
+    Circuit,
+    Namespace,
+}
+
+#[derive(Clone, Default)]
+pub struct Denials {
+    pub counter: Family<DenialLabels, Counter>,
+}
+
+impl Denials {
+    pub fn record(&self, kind: Denied, peer: &PeerId) {
+        warn!(%peer, ?kind, "access denied");
+        self.counter.get_or_create(&DenialLabels { kind }).inc();
+    }
+}
+
+#[derive(Clone)]
+pub struct Access {
+    peers: Arc<HashSet<PeerId>>,
+    namespaces: Arc<HashSet<String>>,
+    pub denials: Denials,
+}
+
+impl Access {
+    // peer ids are checked by Config::validate
+    pub fn new(config: &AccessConfig) -> Self {
+        Self {
+            peers: Arc::new(
+                config
+                    .allowed_peers
+                    .iter()
+                    .map(|peer| peer.parse().expect("validated peer id"))
+                    .collect(),
+            ),
+            namespaces: Arc::new(config.allowed_namespaces.iter().cloned().collect()),
+            denials: Denials::default(),
+        }
+    }
+
+    pub fn restricts_peers(&self) -> bool {
+        !self.peers.is_empty()
+    }
+
+    pub fn allows_peer(&self, peer: &PeerId) -> bool {
+        self.peers.is_empty() || self.peers.contains(peer)
+    }
+
+    pub fn allows_namespace(&self, namespace: &str) -> bool {
+        self.namespaces.is_empty() || self.namespaces.contains(namespace)
+    }
+
+    pub fn limiter(&self, kind: Denied) -> Box<dyn RateLimiter> {
+        Box::new(Allowlist {
+            access: self.clone(),
+            kind,
+        })
+    }
+}
+
+// plugged into the relay as a rate limiter so reservations and circuits
+// of unknown peers are refused by the relay protocol itself
+struct Allowlist {
+    access: Access,
+    kind: Denied,
+}
+
+impl RateLimiter for Allowlist {
+    fn try_next(&mut self, peer: PeerId, _addr: &Multiaddr, _now: Instant) -> bool {
+        let allowed = self.access.allows_peer(&peer);
+        if !allowed {
+            self.access.denials.record(self.kind, &peer);
+        }
+        allowed
+    }
+}
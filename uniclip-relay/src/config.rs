@@ -0,0 +1,257 @@
+use crate::access::{Access, AccessConfig, Denied};
+use anyhow::{anyhow, Context};
+use libp2p::{
+    relay::v2::relay,
+    rendezvous::{self, Ttl},
+    Multiaddr, PeerId,
+};
+use serde::Deserialize;
+use std::{
+    fs,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tracing_subscriber::EnvFilter;
+use uniclip_net::mailbox::MailboxConfig;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub keypair: PathBuf,
+    pub listen_addrs: Vec<String>,
+    // addresses announced to peers, for hosts behind NAT or a load balancer
+    pub external_addrs: Vec<String>,
+    // serve prometheus metrics over http, e.g. "127.0.0.1:9090"
+    pub metrics: Option<String>,
+    // level or filter directives, RUST_LOG takes precedence
+    pub log_level: String,
+
+    pub relay: RelayLimits,
+    pub rendezvous: RendezvousConfig,
+    pub mailbox: MailboxSettings,
+    pub access: AccessConfig,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            keypair: PathBuf::from("keypair"),
+            listen_addrs: Vec::new(),
+            external_addrs: Vec::new(),
+            metrics: None,
+            log_level: "info".to_string(),
+
+            relay: RelayLimits::default(),
+            rendezvous: RendezvousConfig::default(),
+            mailbox: MailboxSettings::default(),
+            access: AccessConfig::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RelayLimits {
+    pub max_reservations: usize,
+    pub max_reservations_per_peer: usize,
+    pub reservation_duration_secs: u64,
+
+    pub max_circuits: usize,
+    pub max_circuits_per_peer: usize,
+    pub max_circuit_duration_secs: u64,
+    pub max_circuit_bytes: u64,
+}
+
+impl Default for RelayLimits {
+    fn default() -> Self {
+        let config = relay::Config::default();
+        Self {
+            max_reservations: config.max_reservations,
+            max_reservations_per_peer: config.max_reservations_per_peer,
+            reservation_duration_secs: config.reservation_duration.as_secs(),
+
+            max_circuits: config.max_circuits,
+            max_circuits_per_peer: config.max_circuits_per_peer,
+            max_circuit_duration_secs: config.max_circuit_duration.as_secs(),
+            max_circuit_bytes: config.max_circuit_bytes,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RendezvousConfig {
+    // seconds
+    pub min_ttl: Ttl,
+    pub max_ttl: Ttl,
+    // registrations kept at most, more are refused until some expire
+    pub max_registrations: usize,
+    pub max_registrations_per_peer: usize,
+}
+
+impl Default for RendezvousConfig {
+    fn default() -> Self {
+        Self {
+            min_ttl: rendezvous::MIN_TTL,
+            max_ttl: rendezvous::MAX_TTL,
+            max_registrations: 10_000,
+            max_registrations_per_peer: 8,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MailboxSettings {
+    // keep the latest encrypted items of each group for offline peers
+    pub enabled: bool,
+    pub size: usize,
+    pub ttl_secs: u64,
+    // bounds across all groups, the oldest items are dropped first
+    pub groups: usize,
+    pub bytes: usize,
+}
+
+impl Default for MailboxSettings {
+    fn default() -> Self {
+        let config = MailboxConfig::default();
+        Self {
+            enabled: false,
+            size: config.size,
+            ttl_secs: config.ttl.as_secs(),
+            groups: config.groups,
+            bytes: config.bytes,
+        }
+    }
+}
+
+impl Config {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        toml::from_str(&content).with_context(|| format!("invalid config in {}", path.display()))
+    }
+
+    pub fn validate(&self) -> anyhow::Result<()> {
+        let mut errors = Vec::new();
+
+        if self.listen_addrs.is_empty() {
+            errors.push("listen_addrs: at least one address is required".to_string());
+        }
+        for (field, addresses) in [
+            ("listen_addrs", &self.listen_addrs),
+            ("external_addrs", &self.external_addrs),
+        ] {
+            for address in addresses {
+                if let Err(err) = address.parse::<Multiaddr>() {
+                    errors.push(format!("{field}: {address:?}: {err}"));
+                }
+            }
+        }
+
+        if let Some(metrics) = &self.metrics {
+            if let Err(err) = metrics.parse::<SocketAddr>() {
+                errors.push(format!("metrics: {metrics:?}: {err}"));
+            }
+        }
+
+        if let Err(err) = EnvFilter::try_new(&self.log_level) {
+            errors.push(format!("log_level: {:?}: {err}", self.log_level));
+        }
+
+        for peer in &self.access.allowed_peers {
+            if let Err(err) = peer.parse::<PeerId>() {
+                errors.push(format!("access.allowed_peers: {peer:?}: {err}"));
+            }
+        }
+
+        if self.relay.reservation_duration_secs == 0 {
+            errors.push("relay.reservation_duration_secs: must be greater than 0".to_string());
+        }
+        if self.relay.max_circuit_duration_secs == 0 {
+            errors.push("relay.max_circuit_duration_secs: must be greater than 0".to_string());
+        }
+        if self.mailbox.enabled && self.mailbox.size == 0 {
+            errors.push("mailbox.size: must be greater than 0".to_string());
+        }
+        if self.mailbox.enabled && self.mailbox.groups == 0 {
+            errors.push("mailbox.groups: must be greater than 0".to_string());
+        }
+        if self.mailbox.enabled && self.mailbox.bytes < uniclip_net::MAX_MESSAGE_SIZE {
+            errors.push(format!(
+                "mailbox.bytes: must be at least {} to hold the largest item",
+                uniclip_net::MAX_MESSAGE_SIZE
+            ));
+        }
+        if self.rendezvous.min_ttl > self.rendezvous.max_ttl {
+            errors.push("rendezvous.min_ttl: must not be greater than max_ttl".to_string());
+        }
+        if self.rendezvous.max_registrations_per_peer > self.rendezvous.max_registrations {
+            errors.push(
+                "rendezvous.max_registrations_per_peer: must not be greater than max_registrations"
+                    .to_string(),
+            );
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(anyhow!("invalid config:\n  {}", errors.join("\n  ")))
+        }
+    }
+
+    pub fn listen_addrs(&self) -> Vec<Multiaddr> {
+        parse(&self.listen_addrs)
+    }
+
+    pub fn external_addrs(&self) -> Vec<Multiaddr> {
+        parse(&self.external_addrs)
+    }
+
+    pub fn metrics_addr(&self) -> Option<SocketAddr> {
+        self.metrics
+            .as_ref()
+            .map(|addr| addr.parse().expect("validated address"))
+    }
+
+    pub fn relay_config(&self, access: &Access) -> relay::Config {
+        let mut config = relay::Config {
+            max_reservations: self.relay.max_reservations,
+            max_reservations_per_peer: self.relay.max_reservations_per_peer,
+            reservation_duration: Duration::from_secs(self.relay.reservation_duration_secs),
+
+            max_circuits: self.relay.max_circuits,
+            max_circuits_per_peer: self.relay.max_circuits_per_peer,
+            max_circuit_duration: Duration::from_secs(self.relay.max_circuit_duration_secs),
+            max_circuit_bytes: self.relay.max_circuit_bytes,
+            ..Default::default()
+        };
+        if access.restricts_peers() {
+            config
+                .reservation_rate_limiters
+                .push(access.limiter(Denied::Reservation));
+            config
+                .circuit_src_rate_limiters
+                .push(access.limiter(Denied::Circuit));
+        }
+        config
+    }
+
+    pub fn mailbox_config(&self) -> Option<MailboxConfig> {
+        self.mailbox.enabled.then(|| MailboxConfig {
+            size: self.mailbox.size,
+            ttl: Duration::from_secs(self.mailbox.ttl_secs),
+            groups: self.mailbox.groups,
+            bytes: self.mailbox.bytes,
+        })
+    }
+}
+
+// only called after validate
+fn parse(addresses: &[String]) -> Vec<Multiaddr> {
+    addresses
+        .iter()
+        .map(|address| address.parse().expect("validated address"))
+        .collect()
+}
@@ -2,7 +2,8 @@ use clap::Parser;
 use futures::{executor::block_on, stream::StreamExt};
 use libp2p::{
     autonat,
-    core::upgrade,
+    bandwidth::BandwidthLogging,
+    core::{transport::OrTransport, upgrade},
     identify::{Identify, IdentifyConfig, IdentifyEvent},
     identity::Keypair,
     mplex,
@@ -10,82 +11,220 @@ use libp2p::{
     noise,
     ping::{Ping, PingConfig, PingEvent},
     relay::v2::relay::{self, Relay},
-    rendezvous,
-    swarm::{Swarm, SwarmEvent},
+    request_response::{
+        ProtocolSupport, RequestResponse, RequestResponseConfig, RequestResponseEvent,
+        RequestResponseMessage,
+    },
+    swarm::{toggle::Toggle, AddressScore, Swarm, SwarmEvent},
     tcp::TcpConfig,
+    websocket::WsConfig,
     Multiaddr, NetworkBehaviour, PeerId, Transport,
 };
+use prometheus_client::registry::Registry;
 use std::{
     fs,
     io::{Read, Write},
+    iter,
     net::{Ipv4Addr, Ipv6Addr},
+    path::{Path, PathBuf},
+    process,
 };
+use tracing::{debug, info, warn};
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_subscriber::EnvFilter;
+use uniclip_net::mailbox::{
+    Mailbox, MailboxCodec, MailboxProtocol, MailboxRequest, MailboxResponse,
+};
+
+mod access;
+use access::Access;
+mod config;
+use config::Config;
+mod metrics;
+mod rendezvous;
+use rendezvous::{RendezvousCodec, RendezvousProtocol};
 
 fn main() {
-    env_logger::init();
+    let opt = Opt::parse();
+    let config = match load_config(&opt) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("{err:#}");
+            process::exit(1);
+        }
+    };
+    let _log_guard = init_tracing(&opt, &config);
 
-    block_on(run());
+    block_on(run(config));
 }
 
-async fn run() {
-    let opt = Opt::parse();
-    println!("opt: {:?}", opt);
+// flags take precedence over the config file
+fn load_config(opt: &Opt) -> anyhow::Result<Config> {
+    let mut config = match &opt.config {
+        Some(path) => Config::load(path)?,
+        None => Config::default(),
+    };
+
+    if let Some(keypair) = &opt.keypair {
+        config.keypair = keypair.clone();
+    }
+    if !opt.listen.is_empty() {
+        config.listen_addrs = opt.listen.clone();
+    }
+    if let Some(port) = opt.port {
+        // Listen on all interfaces
+        let listen_addr = Multiaddr::empty()
+            .with(match opt.use_ipv6 {
+                Some(true) => Protocol::from(Ipv6Addr::UNSPECIFIED),
+                _ => Protocol::from(Ipv4Addr::UNSPECIFIED),
+            })
+            .with(Protocol::Tcp(port));
+        config.listen_addrs.push(listen_addr.to_string());
+    }
+    if !opt.external.is_empty() {
+        config.external_addrs = opt.external.clone();
+    }
+    if let Some(metrics) = &opt.metrics {
+        config.metrics = Some(metrics.clone());
+    }
+    if let Some(log_level) = &opt.log_level {
+        config.log_level = log_level.clone();
+    }
+
+    config.validate()?;
+    Ok(config)
+}
+
+// the level is checked by Config::validate
+fn init_tracing(opt: &Opt, config: &Config) -> Option<WorkerGuard> {
+    let filter = EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| EnvFilter::new(&config.log_level));
+
+    match &opt.log_file {
+        Some(path) => {
+            let dir = path
+                .parent()
+                .filter(|dir| !dir.as_os_str().is_empty())
+                .unwrap_or_else(|| Path::new("."));
+            let name = path.file_name().expect("invalid log file");
+            let (writer, guard) =
+                tracing_appender::non_blocking(tracing_appender::rolling::never(dir, name));
+            tracing_subscriber::fmt()
+                .with_env_filter(filter)
+                .with_writer(writer)
+                .with_ansi(false)
+                .init();
+            Some(guard)
+        }
+        None => {
+            tracing_subscriber::fmt().with_env_filter(filter).init();
+            None
+        }
+    }
+}
+
+async fn run(config: Config) {
+    info!(?config, "starting relay");
 
-    let (local_key, local_peer_id) = get_local_keypair_peerid("keypair");
-    println!("Local peer id: {:?}", local_peer_id);
+    let (local_key, local_peer_id) = get_local_keypair_peerid(&config.keypair);
+    info!(%local_peer_id, "local peer id");
 
     let noise_keys = noise::Keypair::<noise::X25519Spec>::new()
         .into_authentic(&local_key)
         .expect("Signing libp2p-noise static DH keypair failed.");
 
-    let transport = TcpConfig::new()
-        .nodelay(true)
+    // websocket for browsers, listen on a /ws address to use it
+    let (transport, bandwidth) = BandwidthLogging::new(OrTransport::new(
+        TcpConfig::new().nodelay(true),
+        WsConfig::new(TcpConfig::new().nodelay(true)),
+    ));
+    let transport = transport
         .upgrade(upgrade::Version::V1)
         .authenticate(noise::NoiseConfig::xx(noise_keys).into_authenticated())
         .multiplex(mplex::MplexConfig::new())
         .boxed();
 
-    let behaviour = Behaviour::new(local_key, local_peer_id);
+    let access = Access::new(&config.access);
+    let behaviour = Behaviour::new(local_key, local_peer_id, &config, &access);
     let mut swarm = Swarm::new(transport, behaviour, local_peer_id);
 
-    // Listen on all interfaces
-    let listen_addr = Multiaddr::empty()
-        .with(match opt.use_ipv6 {
-            Some(true) => Protocol::from(Ipv6Addr::UNSPECIFIED),
-            _ => Protocol::from(Ipv4Addr::UNSPECIFIED),
-        })
-        .with(Protocol::Tcp(opt.port));
+    for address in config.listen_addrs() {
+        swarm.listen_on(address).unwrap();
+    }
+    for address in config.external_addrs() {
+        info!(%address, "announce external address");
+        swarm.add_external_address(address, AddressScore::Infinite);
+    }
+
+    let mut mailbox = config.mailbox_config().map(Mailbox::new);
+    let mut rendezvous_server = rendezvous::Server::new(&config.rendezvous, access.clone());
 
-    swarm.listen_on(listen_addr).unwrap();
+    let mut metrics = config.metrics_addr().map(|addr| {
+        let mut registry = Registry::default();
+        let metrics = metrics::Metrics::new(&mut registry, &access.denials);
+        let bandwidth = metrics::Bandwidth::new(&mut registry, bandwidth);
+        metrics::serve(addr, registry, bandwidth).expect("failed to serve metrics");
+        metrics
+    });
 
     loop {
-        match swarm.next().await.unwrap() {
+        let event = swarm.next().await.unwrap();
+        if let Some(metrics) = &mut metrics {
+            metrics.record(&event);
+        }
+
+        match event {
             SwarmEvent::NewListenAddr { address, .. } => {
-                println!("Listening on {:?}", address);
+                info!(%address, "listening");
             }
             SwarmEvent::Behaviour(Event::Relay(event)) => {
-                println!("{:?}", event)
+                info!(?event, "relay");
+            }
+            SwarmEvent::Behaviour(Event::Mailbox(RequestResponseEvent::Message {
+                peer,
+                message:
+                    RequestResponseMessage::Request {
+                        request, channel, ..
+                    },
+            })) => {
+                if let Some(mailbox) = &mut mailbox {
+                    debug!(%peer, "mailbox request");
+                    let response = mailbox.handle(request);
+                    let _ = swarm
+                        .behaviour_mut()
+                        .mailbox
+                        .as_mut()
+                        .map(|behaviour| behaviour.send_response(channel, response));
+                }
+            }
+            SwarmEvent::Behaviour(Event::Rendezvous(RequestResponseEvent::Message {
+                peer,
+                message:
+                    RequestResponseMessage::Request {
+                        request, channel, ..
+                    },
+            })) => {
+                // denied namespaces are refused before they are stored
+                let (response, events) = rendezvous_server.handle(peer, request);
+                for event in &events {
+                    info!(?event, "rendezvous");
+                    if let Some(metrics) = &mut metrics {
+                        metrics.record_rendezvous(event);
+                    }
+                }
+                // unregistrations have no response
+                if let Some(response) = response {
+                    let _ = swarm
+                        .behaviour_mut()
+                        .rendezvous
+                        .send_response(channel, response);
+                }
             }
-            SwarmEvent::Behaviour(Event::Rendezvous(
-                rendezvous::server::Event::PeerRegistered { peer, registration },
-            )) => {
-                log::info!(
-                    "Peer {} registered for namespace '{}'",
-                    peer,
-                    registration.namespace
-                );
+            SwarmEvent::ConnectionEstablished { peer_id, .. } => {
+                debug!(%peer_id, "connection established");
             }
-            SwarmEvent::Behaviour(Event::Rendezvous(
-                rendezvous::server::Event::DiscoverServed {
-                    enquirer,
-                    registrations,
-                },
-            )) => {
-                log::info!(
-                    "Served peer {} with {} registrations",
-                    enquirer,
-                    registrations.len()
-                );
+            SwarmEvent::ConnectionClosed { peer_id, .. } => {
+                debug!(%peer_id, "connection closed");
             }
             _ => {}
         }
@@ -99,20 +238,36 @@ struct Behaviour {
     ping: Ping,
     identify: Identify,
     auto_nat: autonat::Behaviour,
-    rendezvous: rendezvous::server::Behaviour,
+    rendezvous: RequestResponse<RendezvousCodec>,
+    mailbox: Toggle<RequestResponse<MailboxCodec>>,
 }
 
 impl Behaviour {
-    fn new(local_key: Keypair, local_peer_id: PeerId) -> Self {
+    fn new(local_key: Keypair, local_peer_id: PeerId, config: &Config, access: &Access) -> Self {
         Behaviour {
-            relay: Relay::new(local_peer_id, Default::default()),
+            relay: Relay::new(local_peer_id, config.relay_config(access)),
             ping: Ping::new(PingConfig::new()),
             identify: Identify::new(IdentifyConfig::new(
-                "/uniclip/0.1.0".to_string(),
+                uniclip_net::version::PeerVersion {
+                    version: uniclip_net::version::PROTOCOL_VERSION,
+                    capabilities: Default::default(),
+                }
+                .to_protocol_version(),
                 local_key.public(),
             )),
             auto_nat: autonat::Behaviour::new(local_peer_id, autonat::Config::default()),
-            rendezvous: rendezvous::server::Behaviour::new(rendezvous::server::Config::default()),
+            rendezvous: RequestResponse::new(
+                RendezvousCodec,
+                iter::once((RendezvousProtocol, ProtocolSupport::Inbound)),
+                RequestResponseConfig::default(),
+            ),
+            mailbox: Toggle::from(config.mailbox_config().map(|_| {
+                RequestResponse::new(
+                    MailboxCodec::new(uniclip_net::MAX_MESSAGE_SIZE),
+                    iter::once((MailboxProtocol, ProtocolSupport::Inbound)),
+                    RequestResponseConfig::default(),
+                )
+            })),
         }
     }
 }
@@ -123,7 +278,8 @@ enum Event {
     Ping(PingEvent),
     Identify(IdentifyEvent),
     Relay(relay::Event),
-    Rendezvous(rendezvous::server::Event),
+    Rendezvous(RequestResponseEvent<rendezvous::wire::Message, rendezvous::wire::Message>),
+    Mailbox(RequestResponseEvent<MailboxRequest, MailboxResponse>),
 }
 
 impl From<PingEvent> for Event {
@@ -150,13 +306,21 @@ impl From<autonat::Event> for Event {
     }
 }
 
-impl From<rendezvous::server::Event> for Event {
-    fn from(event: rendezvous::server::Event) -> Self {
+impl From<RequestResponseEvent<rendezvous::wire::Message, rendezvous::wire::Message>> for Event {
+    fn from(
+        event: RequestResponseEvent<rendezvous::wire::Message, rendezvous::wire::Message>,
+    ) -> Self {
         Event::Rendezvous(event)
     }
 }
 
-pub fn get_local_keypair_peerid(key_path: &str) -> (Keypair, PeerId) {
+impl From<RequestResponseEvent<MailboxRequest, MailboxResponse>> for Event {
+    fn from(event: RequestResponseEvent<MailboxRequest, MailboxResponse>) -> Self {
+        Event::Mailbox(event)
+    }
+}
+
+pub fn get_local_keypair_peerid(key_path: &Path) -> (Keypair, PeerId) {
     let keypair = match fs::File::open(key_path) {
         Ok(mut file) => {
             let mut buffer = vec![0; file.metadata().unwrap().len() as usize];
@@ -167,8 +331,18 @@ pub fn get_local_keypair_peerid(key_path: &str) -> (Keypair, PeerId) {
         Err(_) => {
             let keypair = Keypair::generate_ed25519();
             let buffer = keypair.to_protobuf_encoding().unwrap();
-            if let Ok(mut file) = fs::File::create(key_path) {
-                file.write(&buffer).unwrap();
+            let saved = match key_path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+                Some(dir) => fs::create_dir_all(dir),
+                None => Ok(()),
+            }
+            .and_then(|_| fs::File::create(key_path))
+            .and_then(|mut file| file.write_all(&buffer));
+            match saved {
+                Ok(_) => info!(path = %key_path.display(), "new keypair saved"),
+                Err(err) => warn!(
+                    path = %key_path.display(),
+                    "saving keypair failed, peer id will change on restart: {err}"
+                ),
             }
             keypair
         }
@@ -181,11 +355,39 @@ pub fn get_local_keypair_peerid(key_path: &str) -> (Keypair, PeerId) {
 #[derive(Debug, Parser)]
 #[clap(name = "libp2p relay")]
 struct Opt {
+    /// TOML config file, flags override its values
+    #[clap(long)]
+    config: Option<PathBuf>,
+
+    /// Keypair file, created if missing. the default is ./keypair
+    #[clap(long)]
+    keypair: Option<PathBuf>,
+
+    /// Multiaddr to listen on, can be repeated
+    #[clap(long)]
+    listen: Vec<String>,
+
+    /// Multiaddr announced to peers, can be repeated
+    #[clap(long)]
+    external: Vec<String>,
+
+    /// Serve prometheus metrics on this address, e.g. 127.0.0.1:9090
+    #[clap(long)]
+    metrics: Option<String>,
+
     /// Determine if the relay listen on ipv6 or ipv4 loopback address. the default is ipv4
     #[clap(long)]
     use_ipv6: Option<bool>,
 
-    /// The port used to listen on all interfaces
+    /// The port used to listen on all interfaces, added to the listen addresses
+    #[clap(long)]
+    port: Option<u16>,
+
+    /// Log level or filter directives, overridden by RUST_LOG [default: info]
+    #[clap(long)]
+    log_level: Option<String>,
+
+    /// Write logs to this file instead of stdout
     #[clap(long)]
-    port: u16,
+    log_file: Option<PathBuf>,
 }
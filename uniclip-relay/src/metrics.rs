@@ -0,0 +1,292 @@
+use crate::{access::Denials, rendezvous, Event};
+use libp2p::{
+    bandwidth::BandwidthSinks,
+    metrics::{Metrics as Libp2pMetrics, Recorder},
+    relay::v2::relay,
+    swarm::SwarmEvent,
+    PeerId,
+};
+use prometheus_client::{
+    encoding::text::{encode, Encode},
+    metrics::{counter::Counter, family::Family, gauge::Gauge},
+    registry::Registry,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    io::{BufRead, BufReader, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+use tracing::{debug, error, info};
+
+// a scraper sends its request right away
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_REQUEST_LINE: u64 = 8 * 1024;
+// scrapers are few, more connections are closed right away
+const MAX_CONNECTIONS: usize = 8;
+
+#[derive(Clone, Hash, PartialEq, Eq, Encode)]
+struct NamespaceLabels {
+    namespace: String,
+}
+
+#[derive(Clone, Hash, PartialEq, Eq, Encode)]
+struct DirectionLabels {
+    direction: Direction,
+}
+
+#[derive(Clone, Hash, PartialEq, Eq, Encode)]
+enum Direction {
+    Inbound,
+    Outbound,
+}
+
+pub struct Metrics {
+    libp2p: Libp2pMetrics,
+
+    connected_peers: Gauge,
+    reservations: Gauge,
+    circuits: Gauge,
+    registrations: Family<NamespaceLabels, Gauge>,
+
+    // state needed to turn events into current counts
+    reservations_by_peer: HashMap<PeerId, u64>,
+    registered: HashSet<(String, PeerId)>,
+}
+
+impl Metrics {
+    pub fn new(registry: &mut Registry, denials: &Denials) -> Self {
+        let libp2p = Libp2pMetrics::new(registry);
+        let sub_registry = registry.sub_registry_with_prefix("uniclip_relay");
+
+        sub_registry.register(
+            "access_denials",
+            "Requests refused by the access control, by kind",
+            Box::new(denials.counter.clone()),
+        );
+
+        let connected_peers = Gauge::default();
+        sub_registry.register(
+            "connected_peers",
+            "Number of peers with at least one open connection",
+            Box::new(connected_peers.clone()),
+        );
+        let reservations = Gauge::default();
+        sub_registry.register(
+            "reservations",
+            "Number of active relay reservations",
+            Box::new(reservations.clone()),
+        );
+        let circuits = Gauge::default();
+        sub_registry.register(
+            "circuits",
+            "Number of open relayed circuits",
+            Box::new(circuits.clone()),
+        );
+        let registrations = Family::default();
+        sub_registry.register(
+            "rendezvous_registrations",
+            "Number of rendezvous registrations by namespace",
+            Box::new(registrations.clone()),
+        );
+
+        Self {
+            libp2p,
+            connected_peers,
+            reservations,
+            circuits,
+            registrations,
+            reservations_by_peer: HashMap::new(),
+            registered: HashSet::new(),
+        }
+    }
+
+    pub fn record<E>(&mut self, event: &SwarmEvent<Event, E>) {
+        self.libp2p.record(event);
+
+        match event {
+            SwarmEvent::ConnectionEstablished {
+                num_established, ..
+            } if num_established.get() == 1 => {
+                self.connected_peers.inc();
+            }
+            SwarmEvent::ConnectionClosed {
+                peer_id,
+                num_established: 0,
+                ..
+            } => {
+                self.connected_peers.dec();
+                // the relay drops reservations of disconnected peers without an event
+                self.remove_reservations(peer_id);
+            }
+            SwarmEvent::Behaviour(Event::Relay(event)) => self.record_relay(event),
+            SwarmEvent::Behaviour(Event::Identify(event)) => self.libp2p.record(event),
+            SwarmEvent::Behaviour(Event::Ping(event)) => self.libp2p.record(event),
+            _ => {}
+        }
+    }
+
+    fn record_relay(&mut self, event: &relay::Event) {
+        self.libp2p.record(event);
+
+        match event {
+            relay::Event::ReservationReqAccepted {
+                src_peer_id,
+                renewed: false,
+            } => {
+                *self.reservations_by_peer.entry(*src_peer_id).or_default() += 1;
+                self.reservations.inc();
+            }
+            relay::Event::ReservationTimedOut { src_peer_id } => {
+                if let Some(count) = self.reservations_by_peer.get_mut(src_peer_id) {
+                    *count -= 1;
+                    self.reservations.dec();
+                    if *count == 0 {
+                        self.reservations_by_peer.remove(src_peer_id);
+                    }
+                }
+            }
+            relay::Event::CircuitReqAccepted { .. } => {
+                self.circuits.inc();
+            }
+            relay::Event::CircuitClosed { .. } => {
+                self.circuits.dec();
+            }
+            _ => {}
+        }
+    }
+
+    fn remove_reservations(&mut self, peer_id: &PeerId) {
+        if let Some(count) = self.reservations_by_peer.remove(peer_id) {
+            self.reservations.dec_by(count);
+        }
+    }
+
+    // the server is not a behaviour of its own, main hands its events over
+    pub fn record_rendezvous(&mut self, event: &rendezvous::Event) {
+        let (namespace, peer, registered) = match event {
+            rendezvous::Event::PeerRegistered { peer, namespace } => (namespace, *peer, true),
+            rendezvous::Event::PeerUnregistered { peer, namespace }
+            | rendezvous::Event::RegistrationExpired { peer, namespace } => {
+                (namespace, *peer, false)
+            }
+        };
+
+        let labels = NamespaceLabels {
+            namespace: namespace.clone(),
+        };
+        let key = (namespace.clone(), peer);
+        let changed = if registered {
+            self.registered.insert(key)
+        } else {
+            self.registered.remove(&key)
+        };
+        if changed {
+            let gauge = self.registrations.get_or_create(&labels);
+            if registered {
+                gauge.inc();
+            } else {
+                gauge.dec();
+            }
+        }
+    }
+}
+
+// libp2p's relay does not report bytes per circuit, on a relay the transport
+// totals are dominated by relayed traffic
+pub struct Bandwidth {
+    sinks: Arc<BandwidthSinks>,
+    bytes: Family<DirectionLabels, Counter>,
+}
+
+impl Bandwidth {
+    pub fn new(registry: &mut Registry, sinks: Arc<BandwidthSinks>) -> Self {
+        let bytes = Family::default();
+        registry.sub_registry_with_prefix("uniclip_relay").register(
+            "transport_bytes",
+            "Bytes sent and received over all connections",
+            Box::new(bytes.clone()),
+        );
+        Self { sinks, bytes }
+    }
+
+    fn update(&self) {
+        for (direction, total) in [
+            (Direction::Inbound, self.sinks.total_inbound()),
+            (Direction::Outbound, self.sinks.total_outbound()),
+        ] {
+            let counter = self.bytes.get_or_create(&DirectionLabels { direction });
+            counter.inc_by(total.saturating_sub(counter.get()));
+        }
+    }
+}
+
+pub fn serve(addr: SocketAddr, registry: Registry, bandwidth: Bandwidth) -> anyhow::Result<()> {
+    let listener = TcpListener::bind(addr)?;
+    info!(%addr, "serving metrics");
+
+    // scrapes may overlap, bandwidth updates must not
+    let shared = Arc::new((registry, Mutex::new(bandwidth)));
+    let connections = Arc::new(AtomicUsize::new(0));
+    std::thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            if connections.fetch_add(1, Ordering::SeqCst) >= MAX_CONNECTIONS {
+                connections.fetch_sub(1, Ordering::SeqCst);
+                debug!("too many metrics connections");
+                continue;
+            }
+            if let Err(err) = stream.set_read_timeout(Some(READ_TIMEOUT)) {
+                connections.fetch_sub(1, Ordering::SeqCst);
+                debug!("set metrics read timeout failed: {err}");
+                continue;
+            }
+            // one slow client must not hold up the scrapes of others
+            let shared = shared.clone();
+            let connections = connections.clone();
+            std::thread::spawn(move || {
+                let (registry, bandwidth) = &*shared;
+                respond(&stream, registry, bandwidth);
+                connections.fetch_sub(1, Ordering::SeqCst);
+            });
+        }
+    });
+
+    Ok(())
+}
+
+fn respond(mut stream: &TcpStream, registry: &Registry, bandwidth: &Mutex<Bandwidth>) {
+    let mut request = String::new();
+    let read = BufReader::new(stream)
+        .take(MAX_REQUEST_LINE)
+        .read_line(&mut request);
+    if read.is_err() {
+        return;
+    }
+
+    let response = match request.split_whitespace().nth(1) {
+        Some("/metrics") => {
+            bandwidth.lock().unwrap().update();
+            let mut body = Vec::new();
+            if let Err(err) = encode(&mut body, registry) {
+                error!("encode metrics failed: {err}");
+                return;
+            }
+            let mut response = format!(
+                "HTTP/1.1 200 OK\r\n\
+                 Content-Type: application/openmetrics-text; version=1.0.0; charset=utf-8\r\n\
+                 Content-Length: {}\r\n\
+                 Connection: close\r\n\r\n",
+                body.len()
+            )
+            .into_bytes();
+            response.extend_from_slice(&body);
+            response
+        }
+        _ => b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_vec(),
+    };
+    let _ = stream.write_all(&response);
+}
@@ -0,0 +1,639 @@
+// the rendezvous protocol of libp2p, served here instead of by libp2p's server
+// because that one stores a registration before anyone can look at its namespace
+use crate::{
+    access::{Access, Denied},
+    config::RendezvousConfig,
+};
+use async_trait::async_trait;
+use futures::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use libp2p::{
+    core::{
+        upgrade::{read_length_prefixed, write_length_prefixed},
+        PeerRecord, ProtocolName, SignedEnvelope,
+    },
+    rendezvous::DEFAULT_TTL,
+    request_response::RequestResponseCodec,
+    PeerId,
+};
+use prost::Message as _;
+use std::{
+    collections::HashMap,
+    io,
+    time::{Duration, Instant},
+};
+use tracing::debug;
+
+pub const PROTOCOL_NAME: &str = "/rendezvous/1.0.0";
+
+// the same bounds as libp2p's codec and namespace type
+const MAX_MESSAGE_SIZE: usize = 1024 * 1024;
+const MAX_NAMESPACE_LEN: usize = 255;
+const MAX_DISCOVER_LIMIT: u64 = 1000;
+
+// rpc.proto of the rendezvous spec
+pub mod wire {
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Message {
+        #[prost(enumeration = "MessageType", optional, tag = "1")]
+        pub r#type: Option<i32>,
+        #[prost(message, optional, tag = "2")]
+        pub register: Option<Register>,
+        #[prost(message, optional, tag = "3")]
+        pub register_response: Option<RegisterResponse>,
+        #[prost(message, optional, tag = "4")]
+        pub unregister: Option<Unregister>,
+        #[prost(message, optional, tag = "5")]
+        pub discover: Option<Discover>,
+        #[prost(message, optional, tag = "6")]
+        pub discover_response: Option<DiscoverResponse>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Register {
+        #[prost(string, optional, tag = "1")]
+        pub ns: Option<String>,
+        #[prost(bytes = "vec", optional, tag = "2")]
+        pub signed_peer_record: Option<Vec<u8>>,
+        #[prost(uint64, optional, tag = "3")]
+        pub ttl: Option<u64>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct RegisterResponse {
+        #[prost(enumeration = "ResponseStatus", optional, tag = "1")]
+        pub status: Option<i32>,
+        #[prost(string, optional, tag = "2")]
+        pub status_text: Option<String>,
+        #[prost(uint64, optional, tag = "3")]
+        pub ttl: Option<u64>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Unregister {
+        #[prost(string, optional, tag = "1")]
+        pub ns: Option<String>,
+        #[prost(bytes = "vec", optional, tag = "2")]
+        pub id: Option<Vec<u8>>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Discover {
+        #[prost(string, optional, tag = "1")]
+        pub ns: Option<String>,
+        #[prost(uint64, optional, tag = "2")]
+        pub limit: Option<u64>,
+        #[prost(bytes = "vec", optional, tag = "3")]
+        pub cookie: Option<Vec<u8>>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct DiscoverResponse {
+        #[prost(message, repeated, tag = "1")]
+        pub registrations: Vec<Register>,
+        #[prost(bytes = "vec", optional, tag = "2")]
+        pub cookie: Option<Vec<u8>>,
+        #[prost(enumeration = "ResponseStatus", optional, tag = "3")]
+        pub status: Option<i32>,
+        #[prost(string, optional, tag = "4")]
+        pub status_text: Option<String>,
+    }
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+    #[repr(i32)]
+    pub enum MessageType {
+        Register = 0,
+        RegisterResponse = 1,
+        Unregister = 2,
+        Discover = 3,
+        DiscoverResponse = 4,
+    }
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+    #[repr(i32)]
+    pub enum ResponseStatus {
+        Ok = 0,
+        EInvalidNamespace = 100,
+        EInvalidSignedPeerRecord = 101,
+        EInvalidTtl = 102,
+        EInvalidCookie = 103,
+        ENotAuthorized = 200,
+        EInternalError = 300,
+        EUnavailable = 400,
+    }
+}
+
+use wire::{MessageType, ResponseStatus};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    PeerRegistered { peer: PeerId, namespace: String },
+    PeerUnregistered { peer: PeerId, namespace: String },
+    RegistrationExpired { peer: PeerId, namespace: String },
+}
+
+struct Registration {
+    id: u64,
+    // the signed envelope as the peer sent it
+    record: Vec<u8>,
+    ttl: u64,
+    expires: Instant,
+}
+
+pub struct Server {
+    min_ttl: u64,
+    max_ttl: u64,
+    max_registrations: usize,
+    max_registrations_per_peer: usize,
+    access: Access,
+    next_id: u64,
+    registrations: HashMap<(String, PeerId), Registration>,
+}
+
+impl Server {
+    pub fn new(config: &RendezvousConfig, access: Access) -> Self {
+        Self {
+            min_ttl: config.min_ttl,
+            max_ttl: config.max_ttl,
+            max_registrations: config.max_registrations,
+            max_registrations_per_peer: config.max_registrations_per_peer,
+            access,
+            next_id: 1,
+            registrations: HashMap::new(),
+        }
+    }
+
+    /// The response to a request of `peer`, none for unregistrations, and what changed.
+    pub fn handle(
+        &mut self,
+        peer: PeerId,
+        message: wire::Message,
+    ) -> (Option<wire::Message>, Vec<Event>) {
+        let mut events = self.expire();
+        let response = match (message.r#type.and_then(MessageType::from_i32), message) {
+            (
+                Some(MessageType::Register),
+                wire::Message {
+                    register: Some(register),
+                    ..
+                },
+            ) => {
+                let namespace = register.ns.clone().unwrap_or_default();
+                let result = self.register(peer, register);
+                let (status, ttl) = match result {
+                    Ok(ttl) => {
+                        events.push(Event::PeerRegistered { peer, namespace });
+                        (ResponseStatus::Ok, Some(ttl))
+                    }
+                    Err(status) => {
+                        debug!(%peer, %namespace, ?status, "registration refused");
+                        (status, None)
+                    }
+                };
+                Some(wire::Message {
+                    r#type: Some(MessageType::RegisterResponse as i32),
+                    register_response: Some(wire::RegisterResponse {
+                        status: Some(status as i32),
+                        status_text: None,
+                        ttl,
+                    }),
+                    ..Default::default()
+                })
+            }
+            (
+                Some(MessageType::Unregister),
+                wire::Message {
+                    unregister: Some(unregister),
+                    ..
+                },
+            ) => {
+                let namespace = unregister.ns.unwrap_or_default();
+                if self
+                    .registrations
+                    .remove(&(namespace.clone(), peer))
+                    .is_some()
+                {
+                    events.push(Event::PeerUnregistered { peer, namespace });
+                }
+                None
+            }
+            (
+                Some(MessageType::Discover),
+                wire::Message {
+                    discover: Some(discover),
+                    ..
+                },
+            ) => {
+                let response = match self.discover(discover) {
+                    Ok((registrations, cookie)) => wire::DiscoverResponse {
+                        registrations,
+                        cookie: Some(cookie),
+                        status: Some(ResponseStatus::Ok as i32),
+                        status_text: None,
+                    },
+                    Err(status) => wire::DiscoverResponse {
+                        status: Some(status as i32),
+                        ..Default::default()
+                    },
+                };
+                Some(wire::Message {
+                    r#type: Some(MessageType::DiscoverResponse as i32),
+                    discover_response: Some(response),
+                    ..Default::default()
+                })
+            }
+            _ => {
+                debug!(%peer, "malformed rendezvous request");
+                None
+            }
+        };
+        (response, events)
+    }
+
+    // the namespace is checked before anything is stored
+    fn register(&mut self, peer: PeerId, register: wire::Register) -> Result<u64, ResponseStatus> {
+        let namespace = register.ns.ok_or(ResponseStatus::EInvalidNamespace)?;
+        if namespace.len() > MAX_NAMESPACE_LEN {
+            return Err(ResponseStatus::EInvalidNamespace);
+        }
+        if !self.access.allows_namespace(&namespace) {
+            self.access.denials.record(Denied::Namespace, &peer);
+            return Err(ResponseStatus::ENotAuthorized);
+        }
+
+        let ttl = register.ttl.unwrap_or(DEFAULT_TTL);
+        if ttl < self.min_ttl || ttl > self.max_ttl {
+            return Err(ResponseStatus::EInvalidTtl);
+        }
+
+        let record = register
+            .signed_peer_record
+            .ok_or(ResponseStatus::EInvalidSignedPeerRecord)?;
+        let envelope = SignedEnvelope::from_protobuf_encoding(&record)
+            .map_err(|_| ResponseStatus::EInvalidSignedPeerRecord)?;
+        let peer_record = PeerRecord::from_signed_envelope(envelope)
+            .map_err(|_| ResponseStatus::EInvalidSignedPeerRecord)?;
+        // only for itself
+        if peer_record.peer_id() != peer {
+            return Err(ResponseStatus::ENotAuthorized);
+        }
+
+        // renewals replace the registration and always fit
+        let key = (namespace, peer);
+        if !self.registrations.contains_key(&key) {
+            let of_peer = self
+                .registrations
+                .keys()
+                .filter(|(_, registered)| *registered == peer)
+                .count();
+            if of_peer >= self.max_registrations_per_peer
+                || self.registrations.len() >= self.max_registrations
+            {
+                return Err(ResponseStatus::EUnavailable);
+            }
+        }
+
+        let id = self.next_id;
+        self.next_id += 1;
+        self.registrations.insert(
+            key,
+            Registration {
+                id,
+                record,
+                ttl,
+                expires: Instant::now() + Duration::from_secs(ttl),
+            },
+        );
+        Ok(ttl)
+    }
+
+    // registrations newer than the cookie, in the order they were made
+    fn discover(
+        &self,
+        discover: wire::Discover,
+    ) -> Result<(Vec<wire::Register>, Vec<u8>), ResponseStatus> {
+        let namespace = discover.ns;
+        let after = match discover.cookie {
+            Some(cookie) => {
+                let (after, cookie_namespace) =
+                    decode_cookie(&cookie).ok_or(ResponseStatus::EInvalidCookie)?;
+                if cookie_namespace != namespace {
+                    return Err(ResponseStatus::EInvalidCookie);
+                }
+                after
+            }
+            None => 0,
+        };
+        let limit = discover
+            .limit
+            .unwrap_or(MAX_DISCOVER_LIMIT)
+            .min(MAX_DISCOVER_LIMIT) as usize;
+
+        let mut found: Vec<_> = self
+            .registrations
+            .iter()
+            .filter(|((registered, _), registration)| {
+                registration.id > after
+                    && namespace
+                        .as_ref()
+                        .map_or(true, |namespace| namespace == registered)
+            })
+            .collect();
+        found.sort_by_key(|(_, registration)| registration.id);
+        found.truncate(limit);
+
+        let last = found
+            .last()
+            .map_or(after, |(_, registration)| registration.id);
+        let registrations = found
+            .into_iter()
+            .map(|((registered, _), registration)| wire::Register {
+                ns: Some(registered.clone()),
+                signed_peer_record: Some(registration.record.clone()),
+                ttl: Some(registration.ttl),
+            })
+            .collect();
+        Ok((registrations, encode_cookie(last, namespace.as_deref())))
+    }
+
+    fn expire(&mut self) -> Vec<Event> {
+        let now = Instant::now();
+        let mut events = Vec::new();
+        self.registrations
+            .retain(|(namespace, peer), registration| {
+                let keep = registration.expires > now;
+                if !keep {
+                    events.push(Event::RegistrationExpired {
+                        peer: *peer,
+                        namespace: namespace.clone(),
+                    });
+                }
+                keep
+            });
+        events
+    }
+}
+
+// the wire format of libp2p's cookie: the id, then the namespace if any
+fn encode_cookie(id: u64, namespace: Option<&str>) -> Vec<u8> {
+    let mut cookie = id.to_be_bytes().to_vec();
+    cookie.extend_from_slice(namespace.unwrap_or_default().as_bytes());
+    cookie
+}
+
+fn decode_cookie(cookie: &[u8]) -> Option<(u64, Option<String>)> {
+    if cookie.len() < 8 {
+        return None;
+    }
+    let (id, namespace) = cookie.split_at(8);
+    let id = u64::from_be_bytes(id.try_into().ok()?);
+    let namespace = String::from_utf8(namespace.to_vec()).ok()?;
+    Some((id, (!namespace.is_empty()).then_some(namespace)))
+}
+
+#[derive(Debug, Clone)]
+pub struct RendezvousProtocol;
+
+impl ProtocolName for RendezvousProtocol {
+    fn protocol_name(&self) -> &[u8] {
+        PROTOCOL_NAME.as_bytes()
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RendezvousCodec;
+
+#[async_trait]
+impl RequestResponseCodec for RendezvousCodec {
+    type Protocol = RendezvousProtocol;
+    type Request = wire::Message;
+    type Response = wire::Message;
+
+    async fn read_request<T>(
+        &mut self,
+        _: &RendezvousProtocol,
+        io: &mut T,
+    ) -> io::Result<Self::Request>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        let data = read_length_prefixed(io, MAX_MESSAGE_SIZE).await?;
+        wire::Message::decode(data.as_slice())
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+
+    async fn read_response<T>(
+        &mut self,
+        _: &RendezvousProtocol,
+        io: &mut T,
+    ) -> io::Result<Self::Response>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        let data = read_length_prefixed(io, MAX_MESSAGE_SIZE).await?;
+        wire::Message::decode(data.as_slice())
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+
+    async fn write_request<T>(
+        &mut self,
+        _: &RendezvousProtocol,
+        io: &mut T,
+        request: Self::Request,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        write_length_prefixed(io, request.encode_to_vec()).await?;
+        io.close().await
+    }
+
+    async fn write_response<T>(
+        &mut self,
+        _: &RendezvousProtocol,
+        io: &mut T,
+        response: Self::Response,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        write_length_prefixed(io, response.encode_to_vec()).await?;
+        io.close().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::access::AccessConfig;
+    use libp2p::identity::Keypair;
+
+    fn server(allowed_namespaces: &[&str]) -> Server {
+        let access = Access::new(&AccessConfig {
+            allowed_namespaces: allowed_namespaces.iter().map(ToString::to_string).collect(),
+            ..Default::default()
+        });
+        Server::new(&RendezvousConfig::default(), access)
+    }
+
+    fn register(server: &mut Server, keypair: &Keypair, namespace: &str) -> ResponseStatus {
+        let record = PeerRecord::new(keypair, vec!["/ip4/127.0.0.1/tcp/4001".parse().unwrap()])
+            .unwrap()
+            .into_signed_envelope()
+            .into_protobuf_encoding();
+        let (response, _) = server.handle(
+            keypair.public().to_peer_id(),
+            wire::Message {
+                r#type: Some(MessageType::Register as i32),
+                register: Some(wire::Register {
+                    ns: Some(namespace.to_string()),
+                    signed_peer_record: Some(record),
+                    ttl: None,
+                }),
+                ..Default::default()
+            },
+        );
+        let status = response.unwrap().register_response.unwrap().status.unwrap();
+        ResponseStatus::from_i32(status).unwrap()
+    }
+
+    fn discover(server: &mut Server, namespace: Option<&str>) -> Vec<String> {
+        let (response, _) = server.handle(
+            PeerId::random(),
+            wire::Message {
+                r#type: Some(MessageType::Discover as i32),
+                discover: Some(wire::Discover {
+                    ns: namespace.map(ToString::to_string),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+        );
+        response
+            .unwrap()
+            .discover_response
+            .unwrap()
+            .registrations
+            .into_iter()
+            .map(|registration| registration.ns.unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn denied_namespaces_are_not_stored() {
+        let mut server = server(&["uniclip"]);
+        let keypair = Keypair::generate_ed25519();
+
+        assert_eq!(
+            register(&mut server, &keypair, "other"),
+            ResponseStatus::ENotAuthorized
+        );
+        assert_eq!(
+            register(&mut server, &keypair, "uniclip"),
+            ResponseStatus::Ok
+        );
+        assert!(discover(&mut server, Some("other")).is_empty());
+        assert_eq!(discover(&mut server, None), vec!["uniclip".to_string()]);
+    }
+
+    #[test]
+    fn registrations_are_bounded() {
+        let mut server = Server::new(
+            &RendezvousConfig {
+                max_registrations: 3,
+                max_registrations_per_peer: 2,
+                ..Default::default()
+            },
+            Access::new(&Default::default()),
+        );
+        let keypair = Keypair::generate_ed25519();
+        assert_eq!(register(&mut server, &keypair, "a"), ResponseStatus::Ok);
+        assert_eq!(register(&mut server, &keypair, "b"), ResponseStatus::Ok);
+        assert_eq!(
+            register(&mut server, &keypair, "c"),
+            ResponseStatus::EUnavailable
+        );
+        // renewing is not a new registration
+        assert_eq!(register(&mut server, &keypair, "a"), ResponseStatus::Ok);
+
+        let other = Keypair::generate_ed25519();
+        assert_eq!(register(&mut server, &other, "a"), ResponseStatus::Ok);
+        assert_eq!(
+            register(&mut server, &other, "b"),
+            ResponseStatus::EUnavailable
+        );
+        assert_eq!(discover(&mut server, None).len(), 3);
+    }
+
+    #[test]
+    fn records_must_be_of_the_sender() {
+        let mut server = server(&[]);
+        let keypair = Keypair::generate_ed25519();
+        let record = PeerRecord::new(&keypair, Vec::new())
+            .unwrap()
+            .into_signed_envelope()
+            .into_protobuf_encoding();
+
+        let (response, events) = server.handle(
+            PeerId::random(),
+            wire::Message {
+                r#type: Some(MessageType::Register as i32),
+                register: Some(wire::Register {
+                    ns: Some("uniclip".to_string()),
+                    signed_peer_record: Some(record),
+                    ttl: None,
+                }),
+                ..Default::default()
+            },
+        );
+        let status = response.unwrap().register_response.unwrap().status;
+        assert_eq!(status, Some(ResponseStatus::ENotAuthorized as i32));
+        assert!(events.is_empty());
+        assert!(discover(&mut server, None).is_empty());
+    }
+
+    #[test]
+    fn cookies_return_only_new_registrations() {
+        let mut server = server(&[]);
+        register(&mut server, &Keypair::generate_ed25519(), "uniclip");
+
+        let (response, _) = server.handle(
+            PeerId::random(),
+            wire::Message {
+                r#type: Some(MessageType::Discover as i32),
+                discover: Some(wire::Discover {
+                    ns: Some("uniclip".to_string()),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+        );
+        let cookie = response.unwrap().discover_response.unwrap().cookie;
+        assert_eq!(
+            decode_cookie(cookie.as_ref().unwrap()),
+            Some((1, Some("uniclip".to_string())))
+        );
+
+        register(&mut server, &Keypair::generate_ed25519(), "uniclip");
+        let (response, _) = server.handle(
+            PeerId::random(),
+            wire::Message {
+                r#type: Some(MessageType::Discover as i32),
+                discover: Some(wire::Discover {
+                    ns: Some("uniclip".to_string()),
+                    cookie,
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            response
+                .unwrap()
+                .discover_response
+                .unwrap()
+                .registrations
+                .len(),
+            1
+        );
+    }
+}
@@ -0,0 +1,104 @@
+use std::sync::mpsc;
+use uniclip_net::{Command, GroupConfig, Multiaddr, Origin, Received};
+use uniclip_proto::{clip_msg::MsgType, ClipMsg};
+use wasm_bindgen::prelude::*;
+
+#[wasm_bindgen]
+pub struct Client {
+    to_net_tx: tokio::sync::mpsc::Sender<Command>,
+    from_net_rx: mpsc::Receiver<Received>,
+    latest: Option<ClipMsg>,
+}
+
+#[wasm_bindgen]
+impl Client {
+    /// Joins the group through a relay reachable over websocket,
+    /// e.g. /dns4/relay.example.com/tcp/443/wss/p2p/<peer id>
+    #[wasm_bindgen(constructor)]
+    pub fn new(topic: String, secret: Option<String>, relay: String) -> Result<Client, JsError> {
+        let relay = relay
+            .parse::<Multiaddr>()
+            .map_err(|err| JsError::new(&format!("invalid relay address: {err}")))?;
+        if uniclip_net::relay_peer_id(&relay).is_none() {
+            return Err(JsError::new("relay address must end with /p2p/<peer id>"));
+        }
+
+        let config = uniclip_net::Config {
+            groups: vec![GroupConfig { topic, secret }],
+            listen_addrs: Vec::new(),
+            relays: vec![relay],
+            device_name: "Browser".to_string(),
+            ..Default::default()
+        };
+
+        let (from_net_tx, from_net_rx) = mpsc::channel();
+        let (to_net_tx, to_net_rx) = tokio::sync::mpsc::channel(10);
+        wasm_bindgen_futures::spawn_local(uniclip_net::trans(config, from_net_tx, to_net_rx));
+
+        Ok(Self {
+            to_net_tx,
+            from_net_rx,
+            latest: None,
+        })
+    }
+
+    /// The latest text item of the group, call it periodically
+    pub fn latest(&mut self) -> Option<String> {
+        while let Ok(Received {
+            clip_msg: msg,
+            origin,
+            ..
+        }) = self.from_net_rx.try_recv()
+        {
+            match msg.typ() {
+                MsgType::Text if !self.stale(&msg, origin) => self.latest = Some(msg),
+                MsgType::Text => {}
+                MsgType::Clear => {
+                    if self.latest.as_ref().map(|latest| latest.id) == Some(msg.id) {
+                        self.latest = None;
+                    }
+                }
+                MsgType::Image => {}
+            }
+        }
+        self.latest.as_ref().map(|msg| msg.text().to_string())
+    }
+
+    /// Publishes text to the group and the relay mailbox
+    pub fn push(&mut self, text: String) -> Result<(), JsError> {
+        let mut msg = ClipMsg {
+            id: rand::random::<u32>().max(1),
+            text: Some(text),
+            timestamp: Some(uniclip_net::now_millis()),
+            ..Default::default()
+        };
+        msg.set_typ(MsgType::Text);
+
+        self.to_net_tx
+            .try_send(Command::Publish(msg.clone()))
+            .map_err(|err| JsError::new(&err.to_string()))?;
+        self.latest = Some(msg);
+        Ok(())
+    }
+
+    // mailbox items older than the one shown, like the desktop; clocks of live peers
+    // may be off, so their items always win
+    fn stale(&self, msg: &ClipMsg, origin: Origin) -> bool {
+        let newest = match origin {
+            Origin::Live => return false,
+            Origin::Mailbox { newest } => newest,
+        };
+        let older = matches!(
+            (msg.timestamp, self.latest.as_ref().and_then(|latest| latest.timestamp)),
+            (Some(timestamp), Some(latest)) if timestamp < latest
+        );
+        !newest || older
+    }
+
+    /// Fetches items the relay mailbox holds for the group, call it periodically
+    pub fn sync(&self) -> Result<(), JsError> {
+        self.to_net_tx
+            .try_send(Command::Sync)
+            .map_err(|err| JsError::new(&err.to_string()))
+    }
+}